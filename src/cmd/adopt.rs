@@ -0,0 +1,336 @@
+use anyhow::{Context, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use toml_edit::DocumentMut;
+
+use crate::cmd::{Adopt, Execute};
+use crate::cmd::include::update_lockfile;
+use crate::cmd::search::{load_catalog, STD_LIB_URL};
+use crate::config_man::{cache_dir, get_registry_index};
+use crate::design::{parse_file, project_sources};
+use crate::git::{blob_hash, fetch_history, find_blobs_in_history, list_repo_blobs, name_from_url, strip_credentials};
+use crate::lockfile::read_lockfile;
+use crate::registry::{archive_files, get_versions, list_packages, open_index, sha256_checksum, PackageVersion};
+use crate::toml::{add_dependency, add_registry_dependency, add_top_module};
+
+/// Where a set of known file contents comes from.
+#[derive(Debug, Clone)]
+enum Origin {
+    Git { url: String, commit: String },
+    Registry { index: String, package: PackageVersion },
+}
+
+/// One revision of a repository or registry package, and the blobs it contains.
+#[derive(Debug)]
+struct Candidate {
+    origin: Origin,
+    /// Blob hash to path within the repository or package.
+    blobs: HashMap<String, String>,
+}
+
+/// Project files matched to one candidate, as `(project path, upstream path)`.
+struct Adoption<'a> {
+    candidate: &'a Candidate,
+    files: Vec<(PathBuf, String)>,
+}
+
+impl Execute for Adopt {
+    async fn execute(&self) -> Result<()> {
+        let tracked: HashSet<PathBuf> = read_lockfile()?.into_iter()
+            .filter_map(|entry| fs::canonicalize(entry.full_path).ok())
+            .collect();
+        let mut project_blobs: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for path in project_sources() {
+            if fs::canonicalize(&path).is_ok_and(|p| tracked.contains(&p)) {
+                continue;
+            }
+            let contents = fs::read(&path).with_context(|| format!("Failed to read '{}'", path.display()))?;
+            project_blobs.entry(blob_hash(&contents)?).or_default().push(path);
+        }
+        if project_blobs.is_empty() {
+            println!("No untracked Verilog files found in the project.");
+            return Ok(());
+        }
+        let wanted: HashSet<String> = project_blobs.keys().cloned().collect();
+
+        let mut candidates = Vec::new();
+        candidates.extend(catalog_candidate(self.offline));
+        candidates.extend(vendor_cache_candidates()?);
+        for url in &self.repo {
+            candidates.extend(history_candidates(url, &wanted, self.offline)?);
+        }
+        candidates.extend(registry_candidates(self.offline));
+
+        let adoptions = assign(&candidates, &project_blobs);
+        if adoptions.is_empty() {
+            println!("None of the {} untracked Verilog file(s) match a known repository or package.", project_blobs.values().map(Vec::len).sum::<usize>());
+            if self.repo.is_empty() {
+                println!("Pass the repository the files were copied from with '--repo <URL>' to search its history.");
+            }
+            return Ok(());
+        }
+
+        let mut entries = Vec::new();
+        for adoption in &adoptions {
+            let top_files = top_files(&adoption.files);
+            match &adoption.candidate.origin {
+                Origin::Git { url, commit } => {
+                    println!("Found {} file(s) from {} at {}:", adoption.files.len(), url, &commit[..commit.len().min(7)]);
+                    // The same upstream paths that `write_adoption` records.
+                    let modules = top_files.iter()
+                        .map(|(_, upstream)| format!("{{ top_module = \"{}\", commit_hash = \"{}\" }}", upstream, commit))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    entries.push(format!("\"{}\" = [{}]", url, modules));
+                }
+                Origin::Registry { package, .. } => {
+                    println!("Found {} file(s) from registry package {}@{}:", adoption.files.len(), package.name, package.version);
+                    let modules = package.top_modules.iter()
+                        .map(|m| format!("{{ top_module = \"{}\", version = \"{}\" }}", m, package.version))
+                        .collect::<Vec<_>>().join(", ");
+                    entries.push(format!("{} = [{}]", package.name, modules));
+                }
+            }
+            for (path, upstream) in &adoption.files {
+                let marker = if top_files.iter().any(|(top, _)| top == path) { " (top)" } else { "" };
+                println!("  {} <- {}{}", path.display(), upstream, marker);
+            }
+        }
+
+        println!();
+        println!("Proposed vpm.toml entries:");
+        println!("[dependencies]");
+        for entry in &entries {
+            println!("{}", entry);
+        }
+
+        if self.write {
+            for adoption in &adoptions {
+                write_adoption(adoption)?;
+            }
+            println!();
+            println!("Added {} dependency entr{} to vpm.toml and recorded the adopted files in vpm.lock.", entries.len(), if entries.len() == 1 { "y" } else { "ies" });
+        } else {
+            println!();
+            println!("Run 'vpm adopt --write' to add these entries to vpm.toml and vpm.lock.");
+        }
+        Ok(())
+    }
+}
+
+/// The standard library at the commit of the cached module catalog.
+fn catalog_candidate(offline: bool) -> Option<Candidate> {
+    let catalog = match load_catalog(offline) {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            eprintln!("Warning: Skipping the standard library: {}", e);
+            return None;
+        }
+    };
+    let blobs = catalog.entries.into_iter()
+        .filter(|entry| !entry.blob.is_empty())
+        .map(|entry| (entry.blob, entry.path))
+        .collect();
+    Some(Candidate { origin: Origin::Git { url: STD_LIB_URL.to_string(), commit: catalog.commit }, blobs })
+}
+
+/// Repository snapshots in vendor archives that were extracted into the cache.
+fn vendor_cache_candidates() -> Result<Vec<Candidate>> {
+    let mut candidates = Vec::new();
+    let Ok(archives) = fs::read_dir(cache_dir()?.join("vendor")) else {
+        return Ok(candidates);
+    };
+    for archive in archives.filter_map(|e| e.ok()) {
+        let root = archive.path();
+        let Some(manifest) = fs::read_to_string(root.join("vendor.toml")).ok().and_then(|m| m.parse::<DocumentMut>().ok()) else {
+            continue;
+        };
+        let Some(repositories) = manifest.get("repository").and_then(|r| r.as_array_of_tables()) else {
+            continue;
+        };
+        for entry in repositories.iter() {
+            let field = |key: &str| entry.get(key).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+            let Ok(blobs) = list_repo_blobs(&root.join(field("path"))) else { continue };
+            candidates.push(Candidate {
+                origin: Origin::Git { url: field("url"), commit: field("commit") },
+                blobs: blobs.into_iter().map(|(path, blob)| (blob, path)).collect(),
+            });
+        }
+    }
+    Ok(candidates)
+}
+
+/// Every commit of `url` that contains at least one of the `wanted` blobs.
+fn history_candidates(url: &str, wanted: &HashSet<String>, offline: bool) -> Result<Vec<Candidate>> {
+    let url = strip_credentials(url);
+    let key = sha256_checksum(url.as_bytes());
+    let repo_path = cache_dir()?.join("adopt").join(format!("{}-{}", name_from_url(&url), &key.trim_start_matches("sha256:")[..8]));
+    if !offline {
+        let pb = ProgressBar::new_spinner();
+        pb.set_style(ProgressStyle::default_spinner().template("{spinner} {msg}").unwrap());
+        pb.set_message(format!("Fetching history of {}...", url));
+        pb.enable_steady_tick(Duration::from_millis(100));
+        fetch_history(&url, &repo_path, Some(&pb))?;
+        pb.finish_and_clear();
+    } else if !repo_path.exists() {
+        anyhow::bail!("No cached history of '{}'. Run 'vpm adopt --repo {}' once while online.", url, url);
+    }
+
+    Ok(find_blobs_in_history(&repo_path, wanted)?.into_iter()
+        .map(|(commit, found)| Candidate {
+            origin: Origin::Git { url: url.clone(), commit },
+            blobs: found.into_iter().collect(),
+        })
+        .collect())
+}
+
+/// Every version of every package in the configured registry whose archive is available
+/// locally. Remote archives are not downloaded.
+fn registry_candidates(offline: bool) -> Vec<Candidate> {
+    let index = get_registry_index();
+    let is_remote = index.contains("://") && !index.starts_with("file://");
+    if offline && is_remote {
+        return Vec::new();
+    }
+    let index_dir = match open_index(&index) {
+        Ok(index_dir) => index_dir,
+        Err(e) => {
+            eprintln!("Warning: Skipping the registry index: {}", e);
+            return Vec::new();
+        }
+    };
+    let mut candidates = Vec::new();
+    for name in list_packages(&index_dir).unwrap_or_default() {
+        for package in get_versions(&index_dir, &name).unwrap_or_default() {
+            if package.source.starts_with("http://") || package.source.starts_with("https://") {
+                continue;
+            }
+            let source = package.source.strip_prefix("file://").unwrap_or(&package.source);
+            let Ok(archive) = fs::read(index_dir.join(source)) else { continue };
+            let Ok(files) = archive_files(&archive) else { continue };
+            let blobs = files.into_iter()
+                .filter_map(|(path, contents)| Some((blob_hash(&contents).ok()?, path)))
+                .collect();
+            candidates.push(Candidate { origin: Origin::Registry { index: index.clone(), package }, blobs });
+        }
+    }
+    candidates
+}
+
+/// Greedily assigns project files to the candidate that explains the most of them, so a
+/// copy of a whole repository is attributed to one revision instead of many.
+fn assign<'a>(candidates: &'a [Candidate], project_blobs: &HashMap<String, Vec<PathBuf>>) -> Vec<Adoption<'a>> {
+    let mut remaining: HashSet<&String> = project_blobs.keys().collect();
+    let mut adoptions = Vec::new();
+    loop {
+        // Earlier candidates win ties, so the newest matching commit is preferred.
+        let best = candidates.iter()
+            .map(|candidate| (candidate, candidate.blobs.keys().filter(|blob| remaining.contains(blob)).count()))
+            .filter(|(_, count)| *count > 0)
+            .fold(None, |best: Option<(&Candidate, usize)>, (candidate, count)| match best {
+                Some((_, best_count)) if best_count >= count => best,
+                _ => Some((candidate, count)),
+            });
+        let Some((candidate, _)) = best else { break };
+
+        let mut files = Vec::new();
+        for (blob, upstream) in &candidate.blobs {
+            if remaining.remove(blob) {
+                for path in &project_blobs[blob] {
+                    files.push((path.clone(), upstream.clone()));
+                }
+            }
+        }
+        files.sort();
+        adoptions.push(Adoption { candidate, files });
+    }
+    adoptions
+}
+
+/// Files whose modules are not instantiated by any other adopted file. Falls back to all
+/// files when the design cannot be parsed.
+fn top_files(files: &[(PathBuf, String)]) -> Vec<(PathBuf, String)> {
+    let mut declared: Vec<(usize, String)> = Vec::new();
+    let mut instantiated: HashSet<String> = HashSet::new();
+    for (i, (path, _)) in files.iter().enumerate() {
+        let Ok(modules) = parse_file(path) else {
+            return files.to_vec();
+        };
+        for module in modules {
+            declared.push((i, module.name));
+            instantiated.extend(module.submodules);
+        }
+    }
+    let tops: Vec<(PathBuf, String)> = files.iter().enumerate()
+        .filter(|(i, _)| declared.iter().any(|(file, name)| file == i && !instantiated.contains(name)))
+        .map(|(_, file)| file.clone())
+        .collect();
+    if tops.is_empty() { files.to_vec() } else { tops }
+}
+
+/// Adds the dependency of `adoption` to vpm.toml and records its files in vpm.lock.
+fn write_adoption(adoption: &Adoption) -> Result<()> {
+    let top_files = top_files(&adoption.files);
+    let source = match &adoption.candidate.origin {
+        Origin::Git { url, commit } => {
+            // The top module is named by its path upstream, which is what sync fetches.
+            add_dependency(url)?;
+            for (_, upstream) in &top_files {
+                add_top_module(url, upstream, commit)?;
+            }
+            url.clone()
+        }
+        Origin::Registry { index, package } => {
+            add_registry_dependency(&package.name, &package.version, &package.top_modules)?;
+            format!("registry+{}#{}@{}", index, package.name, package.version)
+        }
+    };
+
+    // Every adopted module counts as visited so only modules outside the adopted set are
+    // recorded as missing submodules. Local copies may have been renamed, so the names
+    // come from upstream and from the modules the files declare.
+    let visited: HashSet<String> = adoption.files.iter()
+        .filter_map(|(_, upstream)| Path::new(upstream).file_stem().and_then(|s| s.to_str()).map(|s| s.to_string()))
+        .chain(adoption.files.iter().flat_map(|(path, _)| parse_file(path).unwrap_or_default().into_iter().map(|m| m.name)))
+        .collect();
+    for (path, _) in &adoption.files {
+        let contents = fs::read_to_string(path)?;
+        let is_top_module = top_files.iter().any(|(top, _)| top == path);
+        update_lockfile(&path.to_path_buf(), &source, &contents, &visited, is_top_module, None)?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writing_an_adoption_into_an_empty_manifest_adds_the_dependency() {
+        let project = tempfile::tempdir().unwrap();
+        std::env::set_current_dir(project.path()).unwrap();
+        fs::write("vpm.toml", "[package]\nname = \"demo\"\n\n[dependencies]\n").unwrap();
+        fs::write("fifo.v", "module fifo(input clk);\nendmodule\n").unwrap();
+
+        let url = "https://github.com/acme/ip";
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        let candidate = Candidate {
+            origin: Origin::Git { url: url.to_string(), commit: commit.to_string() },
+            blobs: HashMap::new(),
+        };
+        let adoption = Adoption { candidate: &candidate, files: vec![(PathBuf::from("fifo.v"), "rtl/fifo.v".to_string())] };
+        write_adoption(&adoption).unwrap();
+
+        let manifest = fs::read_to_string("vpm.toml").unwrap().parse::<toml_edit::DocumentMut>().unwrap();
+        let modules = manifest["dependencies"][url].as_array().unwrap();
+        assert_eq!(modules.len(), 1);
+        let module = modules.get(0).unwrap().as_inline_table().unwrap();
+        assert_eq!(module.get("top_module").and_then(|v| v.as_str()), Some("rtl/fifo.v"));
+        assert_eq!(module.get("commit_hash").and_then(|v| v.as_str()), Some(commit));
+        assert!(fs::read_to_string("vpm.lock").unwrap().contains(&format!("source = \"{}\"", url)));
+    }
+}
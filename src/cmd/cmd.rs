@@ -11,11 +11,17 @@ use clap::Parser;
 )]
 pub enum Cmd {
     #[command(
-        about = "vpm include <MODULE_URL> [--repo] [--riscv] [--commit <HASH>] // Add a module or repository to your project",
+        about = "vpm include <MODULE_URL | NAME@VERSION | registry:NAME> [--repo] [--riscv] [--commit <HASH>] // Add a module, repository or registry package to your project",
         long_about = "Include a module with one command. VPM's internal parser will identify and configure any subdependencies."
     )]
     Include(Include),
 
+    #[command(
+        about = "vpm publish [--index <PATH>] // Publish this package to a registry index",
+        long_about = "Package the [package] metadata from vpm.toml together with the project's RTL into a tarball and add it to a local or file-based registry index, so it can be included with 'vpm include <NAME>@<VERSION>'."
+    )]
+    Publish(Publish),
+
     #[command(
         about = "vpm update <MODULE_PATH> // Update a module to its latest version",
         long_about = "Update a specific module to its latest version. This command checks for updates to the specified module and applies them if available."
@@ -28,6 +34,30 @@ pub enum Cmd {
     )]
     Remove(Remove),
 
+    #[command(
+        about = "vpm sync [--jobs <N>] // Restore all dependencies listed in vpm.toml",
+        long_about = "Restore all dependencies listed in vpm.toml at their recorded commits. Independent repositories are fetched concurrently."
+    )]
+    Sync(Sync),
+
+    #[command(
+        about = "vpm vendor --archive <FILE> // Bundle all dependencies into a self-contained archive",
+        long_about = "Bundle vpm.toml, vpm.lock, every dependency at its locked commit and any local patches to vendored files into a single .tar.gz archive. The archive can be delivered to machines without network access and restored with 'vpm sync --archive <FILE>'."
+    )]
+    Vendor(Vendor),
+
+    #[command(
+        about = "vpm generate [NAME] [--force] // Run generator dependencies and include their Verilog",
+        long_about = "Run the generators declared under [generators] in vpm.toml. Each generator's repository is fetched at its commit, its command is run with the declared parameters, and the Verilog it writes to its output directory is cached by commit and parameters. The top module of the output is then included like any other dependency."
+    )]
+    Generate(Generate),
+
+    #[command(
+        about = "vpm adopt [--repo <URL>]... [--write] // Detect copied third-party IP and turn it into dependencies",
+        long_about = "Scan the project's Verilog files that are not yet tracked in vpm.lock and match them by content hash against the standard library, cached vendor archives, the registry index and the history of any repositories given with --repo. Prints the vpm.toml entries that would track the matched files at the commit or version they were copied from, and writes them with --write."
+    )]
+    Adopt(Adopt),
+
     #[command(
         about = "vpm dotf <TOP_MODULE_PATH> // Generate a .f filelist for a module",
         long_about = "Generate a filelist (.f file) for a top module and all its submodules."
@@ -47,11 +77,41 @@ pub enum Cmd {
     Install(Install),
 
     #[command(
-        about = "vpm list // List all available modules in the project",
-        long_about = "List all available modules in the current project. This command provides an overview of all modules currently included in your project, helping you keep track of your dependencies and project structure."
+        about = "vpm list [--installed] // List available modules or the project's dependencies",
+        long_about = "List all modules available in the standard library. With --installed, list the dependencies of the current project instead: their top modules, commit or version, number of vendored files, whether any vendored file was modified locally, and license."
     )]
     List(List),
 
+    #[command(
+        about = "vpm search [QUERY] // Search the standard library for modules",
+        long_about = "Search the standard library for modules by name or description. Results come from a locally cached module index that is refreshed incrementally, and can be filtered by category or license."
+    )]
+    Search(Search),
+
+    #[command(
+        about = "vpm info <MODULE> // Show the interface, origin and dependents of a module",
+        long_about = "Show everything needed to use a module in the project: its parameters with defaults, its ports with direction and width, the file and package it comes from, the commit it is locked to, the submodules it instantiates and the project modules that instantiate it."
+    )]
+    Info(Info),
+
+    #[command(
+        about = "vpm graph <TOP> [--format <dot|mermaid>] // Draw the module hierarchy below a top module",
+        long_about = "Draw the module hierarchy below a top module as a Graphviz DOT or Mermaid graph, for design reviews and documentation. Each module appears once; vendor primitives are drawn as black boxes and modules that are not in the project are marked. Optionally groups modules by the package they come from and labels edges with instance counts and parameter overrides."
+    )]
+    Graph(Graph),
+
+    #[command(
+        about = "vpm lint [PATHS]... [--top <MODULE>] // Lint the design with Verible and Verilator",
+        long_about = "Lint the design with verible-verilog-lint and 'verilator --lint-only -Wall', whichever are installed, and report their findings in one format: file:line:col, severity, message and rule. Without paths, lints the files the top module needs, the sources of a target, or the project's own sources. Findings matching a [[waiver]] in the waiver files listed under [lint] in vpm.toml or given with --waivers are dropped. Exits with a non-zero status if errors remain."
+    )]
+    Lint(Lint),
+
+    #[command(
+        about = "vpm export [--format <fusesoc|edam>] [--top <MODULE>] // Describe the project for FuseSoC or Edalize",
+        long_about = "Generate a FuseSoC core file or Edalize EDAM descriptions of the project. The files needed by the top module are listed in dependency order, with headers marked as include files and the defines recorded in vpm.lock. Both formats get a 'sim' target (Icarus Verilog, using the testbench if there is one) and a 'synth' target (Yosys)."
+    )]
+    Export(Export),
+
     #[command(
         about = "vpm sim <FILE_PATHS>... // Simulate Verilog files",
         long_about = "Simulate one or more Verilog files. This command runs simulations on the specified Verilog files, allowing you to test and verify the behavior of your designs before synthesis or implementation."
@@ -89,7 +149,7 @@ pub enum Cmd {
 
     #[command(
         about = "vpm config <KEY> <VALUE> // Configure VPM settings",
-        long_about = "Configure VPM settings. This command allows you to set various options and preferences for VPM, such as enabling or disabling analytics."
+        long_about = "Configure VPM settings. This command allows you to set various options and preferences for VPM, such as enabling or disabling analytics, access tokens for private repositories, and URL rewrite rules."
     )]
     Config(Config),
 }
@@ -101,12 +161,28 @@ pub struct Upgrade {}
 pub struct Include {
     #[arg(long, short, help = "If this flag is set, the URL will be treated as a full repository. If not set, the URL will be treated as a single module.")]
     pub repo: bool,
-    #[arg(help = "GitHub URL of the module to include. This should point to a single .v or .sv file in GitHub. If --repo is set, <URL> should not be a full repository URL, but rather 'AUTHOR_NAME/REPO_NAME'")]
+    #[arg(help = "GitHub URL of the module to include, or 'NAME@VERSION' of a package in the registry index ('registry:NAME' for its latest version). A URL should point to a single .v or .sv file in GitHub. If --repo is set, <URL> should be 'AUTHOR_NAME/REPO_NAME' for GitHub, or a full HTTPS or SSH repository URL for private or non-GitHub hosts")]
     pub url: String,
     #[arg(long, help = "Include RISC-V specific modules. Use this flag when including modules designed specifically for RISC-V architectures.")]
     pub riscv: bool,
     #[arg(long, help = "Commit hash of the module to include. This should be a valid commit hash from the module's repository.")]
     pub commit: Option<String>,
+    #[arg(long, help = "Vendor archive created with 'vpm vendor' to include from instead of fetching from the network")]
+    pub archive: Option<String>,
+    #[arg(long = "define", short = 'D', value_name = "NAME[=VALUE]", help = "Define a macro for this configuration. Sources are preprocessed with these defines, so only the modules, includes and packages in active `ifdef branches are vendored. Without any defines, every branch is followed. Can be repeated.")]
+    pub define: Vec<String>,
+    #[arg(long, help = "Write a synthesizable black box stub, a '(* blackbox *)' module with the same parameters and ports, to headers/<MODULE>_blackbox.v for each included module")]
+    pub blackbox: bool,
+    #[arg(long, help = "Write an instantiation template with every parameter and port connection to headers/<MODULE>_inst.vh for each included module")]
+    pub template: bool,
+    #[arg(long, help = "Write the parameters and ports of each included module as JSON to headers/<MODULE>.json")]
+    pub interface_json: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct Publish {
+    #[arg(long, help = "Path of the registry index directory to publish to. Defaults to the index configured with 'vpm config --registry'.")]
+    pub index: Option<String>,
 }
 
 #[derive(Debug, Parser)]
@@ -117,6 +193,38 @@ pub struct Update {
     pub commit: Option<String>,
 }
 
+#[derive(Debug, Parser)]
+pub struct Sync {
+    #[arg(long, short, help = "Maximum number of repositories to fetch at the same time. Defaults to the number of available CPUs.")]
+    pub jobs: Option<usize>,
+    #[arg(long, help = "Vendor archive created with 'vpm vendor' to restore dependencies from instead of fetching from the network")]
+    pub archive: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct Generate {
+    #[arg(help = "Name of the generator to run. Runs every generator if omitted.")]
+    pub name: Option<String>,
+    #[arg(long, help = "Run the generator even if its output is cached")]
+    pub force: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct Vendor {
+    #[arg(long, help = "Path of the .tar.gz archive to create")]
+    pub archive: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct Adopt {
+    #[arg(long, help = "Repository the files may have been copied from. Its full history is searched for matching file contents. Can be given multiple times.")]
+    pub repo: Vec<String>,
+    #[arg(long, help = "Add the proposed entries to vpm.toml and record the adopted files in vpm.lock")]
+    pub write: bool,
+    #[arg(long, help = "Only use cached indexes and repository histories")]
+    pub offline: bool,
+}
+
 #[derive(Debug, Parser)]
 pub struct Remove {
     #[arg(help = "Full module path of the package to remove. This should be the complete path to the package directory within your project structure.")]
@@ -127,6 +235,10 @@ pub struct Remove {
 pub struct Dotf {
     #[arg(help = "Path to the top module to generate a filelist for. This should be the complete path to the top module file within your project structure.")]
     pub path_to_top_module: String,
+    #[arg(long, help = "Select sources for this target (sim, synth, fpga, asic or test) from the [[sources]] filesets and dependencies in vpm.toml. Writes '<TOP>_<TARGET>.f' next to the regular filelist.")]
+    pub target: Option<String>,
+    #[arg(long = "define", short = 'D', value_name = "NAME[=VALUE]", help = "Define a macro for this configuration. Sources are preprocessed with these defines, so only modules in active `ifdef branches are followed, and each define is written to the filelist as +define+. Can be repeated.")]
+    pub define: Vec<String>,
 }
 
 #[derive(Debug, Parser)]
@@ -159,10 +271,79 @@ pub struct Sim {
     #[arg(long, help = "Generate waveform output. If set, the simulation will produce waveform data and open it in GTKWave.")]
     pub waveform: bool,
     pub folder: Option<String>,
+    #[arg(long, help = "Select sources for this target (sim, synth, fpga, asic or test) from the [[sources]] filesets and dependencies in vpm.toml, and compile them along with the given files.")]
+    pub target: Option<String>,
 }
 
 #[derive(Debug, Parser)]
-pub struct List {}
+pub struct List {
+    #[arg(long, help = "List the dependencies of the current project instead of the standard library")]
+    pub installed: bool,
+    #[arg(long, requires = "installed", help = "Print the installed dependencies as JSON")]
+    pub json: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct Info {
+    #[arg(help = "Name of the module, or path to the file that declares it")]
+    pub module: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct Graph {
+    #[arg(help = "Name of the top module, or path to the file that declares it")]
+    pub top: String,
+    #[arg(long, default_value = "dot", help = "Output format: 'dot' for Graphviz, or 'mermaid'")]
+    pub format: String,
+    #[arg(long, short, help = "File to write the graph to. Printed to stdout if not set.")]
+    pub output: Option<String>,
+    #[arg(long, help = "Group modules by the package they were included from")]
+    pub cluster: bool,
+    #[arg(long, help = "Label edges with the number of instances and their parameter overrides")]
+    pub annotate: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct Lint {
+    #[arg(help = "Files or directories to lint")]
+    pub paths: Vec<String>,
+    #[arg(long, help = "Lint the top module and every file it depends on. Also passed to Verilator as the top module.")]
+    pub top: Option<String>,
+    #[arg(long, help = "Lint the sources of this target (sim, synth, fpga, asic or test) from the [[sources]] filesets and dependencies in vpm.toml")]
+    pub target: Option<String>,
+    #[arg(long, help = "Linter to run: 'verible' or 'verilator'. Can be repeated. Runs every installed linter if not set.")]
+    pub tool: Vec<String>,
+    #[arg(long, help = "TOML file of [[waiver]] entries (tool, rule, file, line, message) to apply in addition to those listed under [lint] waivers in vpm.toml. Can be repeated.")]
+    pub waivers: Vec<String>,
+    #[arg(long = "define", short = 'D', value_name = "NAME[=VALUE]", help = "Define a macro for the linters. Can be repeated.")]
+    pub define: Vec<String>,
+    #[arg(long, help = "Exit with a non-zero status on warnings too")]
+    pub deny_warnings: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct Export {
+    #[arg(long, default_value = "fusesoc", help = "Output format: 'fusesoc' for a .core file, or 'edam' for Edalize EDAM JSON")]
+    pub format: String,
+    #[arg(long, help = "Top module of the design. Detected automatically if the project has a single top.")]
+    pub top: Option<String>,
+    #[arg(long, help = "Only export this EDAM target ('sim' or 'synth'). Both are exported if omitted.")]
+    pub target: Option<String>,
+    #[arg(long, short, help = "Path of the file to write. Defaults to '<name>.core' or '<name>_<target>.eda.json'.")]
+    pub output: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct Search {
+    #[arg(help = "Text to fuzzy match against module names and descriptions. Lists every module if omitted.")]
+    pub query: Option<String>,
+    #[arg(long, help = "Only show modules in this category (the top-level directory of the standard library)")]
+    pub category: Option<String>,
+    #[arg(long, help = "Only show modules whose license contains this text, e.g. 'MIT'")]
+    pub license: Option<String>,
+    #[arg(long, help = "Use the cached module index without checking for updates")]
+    pub offline: bool,
+}
 
 #[derive(Debug, Parser)]
 pub struct Synth {
@@ -179,6 +360,8 @@ pub struct Synth {
     pub board: Option<String>,
     #[arg(long, help = "Generate synthesis script. If set, the command will produce a Yosys synthesis script instead of running the synthesis directly.")]
     pub gen_yosys_script: bool,
+    #[arg(long, help = "Select sources for this target (sim, synth, fpga, asic or test) from the [[sources]] filesets and dependencies in vpm.toml, and read them along with the top module.")]
+    pub target: Option<String>,
 }
 
 #[derive(Debug, Parser)]
@@ -203,4 +386,12 @@ pub struct Run {
 pub struct Config {
     #[arg(long, help = "Enable or disable anonymous usage data collection. Set to false to opt-out of data collection.")]
     pub analytics: Option<bool>,
+    #[arg(long, value_name = "HOST=TOKEN", help = "Store an access token for a git host (e.g. 'gitlab.example.com=glpat-...') in the user config. Tokens are never written to vpm.toml or vpm.lock.")]
+    pub token: Option<String>,
+    #[arg(long, value_name = "PREFIX=REPLACEMENT", help = "Rewrite repository URLs starting with PREFIX to start with REPLACEMENT instead, like git's 'insteadOf' (e.g. 'https://github.com/acme/=git@github.com:acme/').")]
+    pub url_rewrite: Option<String>,
+    #[arg(long, value_name = "PATH_OR_URL", help = "Set the package registry index used by 'vpm include <NAME>@<VERSION>' and 'vpm publish'. This can be a local directory or a git repository URL.")]
+    pub registry: Option<String>,
+    #[arg(long, help = "Enable or disable partial clones, which download only the files that are vendored instead of the whole commit. Requires the git command line on PATH.")]
+    pub partial_clone: Option<bool>,
 }
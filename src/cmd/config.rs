@@ -1,6 +1,6 @@
 use crate::cmd::{Execute, Config};
-use crate::config_man::set_analytics;
-use anyhow::Result;
+use crate::config_man::{set_analytics, set_credentials, set_partial_clone, set_registry_index, set_url_rewrite};
+use anyhow::{anyhow, Result};
 
 impl Execute for Config {
     async fn execute(&self) -> Result<()> {
@@ -8,6 +8,24 @@ impl Execute for Config {
             set_analytics(self.analytics.unwrap())?;
             println!("Analytics set to: {}", self.analytics.unwrap());
         }
+        if let Some(token) = &self.token {
+            let (host, token) = token.split_once('=').ok_or_else(|| anyhow!("Expected --token <HOST>=<TOKEN>"))?;
+            set_credentials(host, token)?;
+            println!("Access token stored for: {}", host);
+        }
+        if let Some(rule) = &self.url_rewrite {
+            let (prefix, replacement) = rule.split_once('=').ok_or_else(|| anyhow!("Expected --url-rewrite <PREFIX>=<REPLACEMENT>"))?;
+            set_url_rewrite(prefix, replacement)?;
+            println!("URLs starting with '{}' will be fetched from '{}'", prefix, replacement);
+        }
+        if let Some(registry) = &self.registry {
+            set_registry_index(registry)?;
+            println!("Registry index set to: {}", registry);
+        }
+        if let Some(partial_clone) = self.partial_clone {
+            set_partial_clone(partial_clone)?;
+            println!("Partial clones set to: {}", partial_clone);
+        }
         Ok(())
     }
 }
\ No newline at end of file
@@ -8,6 +8,7 @@ use std::process::{Command, Stdio};
 
 use crate::cmd::{Execute, Docs};
 use crate::config_man::{decrypt_docs_count, encrypt_docs_count};
+use crate::design::DesignIndex;
 
 impl Execute for Docs {
     async fn execute(&self) -> Result<()> {
@@ -33,8 +34,15 @@ impl Execute for Docs {
                     .context("Failed to generate documentation. Please check the module content and try again.")?;
             }
         } else {
-            let full_module_path = PathBuf::from(&self.module_path);
-            
+            let mut full_module_path = PathBuf::from(&self.module_path);
+            if !full_module_path.exists() {
+                // Also accept the name of a module declared anywhere in the project.
+                let name = self.module_path.trim_end_matches(".sv").trim_end_matches(".v");
+                if let Some((path, _)) = DesignIndex::load().find_module(name) {
+                    full_module_path = path.to_path_buf();
+                }
+            }
+
             if full_module_path.exists() {
                 let content = fs::read_to_string(&full_module_path)
                     .with_context(|| format!("Failed to read module file: {}. Please ensure you have read permissions for this file.", full_module_path.display()))?;
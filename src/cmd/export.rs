@@ -0,0 +1,243 @@
+use anyhow::{Context, Result};
+use serde_json::json;
+use serde_yaml::{Mapping, Value};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cmd::{Execute, Export};
+use crate::design::{project_sources, push_unique, DesignIndex, ModuleInterface};
+use crate::lockfile::read_lockfile;
+use crate::preprocess::parse_define;
+use crate::toml::get_package;
+
+/// Tools used by the generated targets, matching the ones `vpm sim` and `vpm synth` drive.
+const SIM_TOOL: &str = "icarus";
+const SYNTH_TOOL: &str = "yosys";
+
+/// A source file of the exported design, relative to the project root.
+#[derive(Debug, Clone)]
+struct SourceFile {
+    path: String,
+    file_type: &'static str,
+    is_include_file: bool,
+}
+
+/// The files, tops and defines shared by every export format.
+#[derive(Debug)]
+struct ExportedDesign {
+    name: String,
+    version: String,
+    description: String,
+    toplevel: String,
+    testbench: Option<String>,
+    files: Vec<SourceFile>,
+    testbench_files: Vec<SourceFile>,
+    defines: Vec<(String, Option<String>)>,
+}
+
+impl Execute for Export {
+    async fn execute(&self) -> Result<()> {
+        let design = collect_design(self.top.as_deref())?;
+        match self.format.as_str() {
+            "fusesoc" => {
+                let output = self.output.clone().unwrap_or_else(|| format!("{}.core", design.name));
+                fs::write(&output, fusesoc_core(&design)?)?;
+                println!("Wrote FuseSoC core {} (toplevel '{}', {} files)", output, design.toplevel, design.files.len());
+            }
+            "edam" => {
+                let targets: Vec<&str> = match self.target.as_deref() {
+                    Some(target) => vec![target],
+                    None => vec!["sim", "synth"],
+                };
+                for target in targets {
+                    let output = match &self.output {
+                        Some(output) if self.target.is_some() => output.clone(),
+                        _ => format!("{}_{}.eda.json", design.name, target),
+                    };
+                    fs::write(&output, serde_json::to_string_pretty(&edam(&design, target)?)?)?;
+                    println!("Wrote EDAM description {} for target '{}'", output, target);
+                }
+            }
+            other => anyhow::bail!("Unknown export format '{}'. Use 'fusesoc' or 'edam'.", other),
+        }
+        Ok(())
+    }
+}
+
+fn collect_design(top: Option<&str>) -> Result<ExportedDesign> {
+    let package = get_package();
+    let sources: Vec<PathBuf> = project_sources().into_iter()
+        .filter(|path| !path.components().any(|c| c.as_os_str() == "headers"))
+        .collect();
+    let design = DesignIndex::from_files(&sources);
+    let modules: HashMap<&str, &ModuleInterface> = design.modules().map(|(_, m)| (m.name.as_str(), m)).collect();
+
+    let toplevel = match top {
+        Some(top) => top.trim_end_matches(".sv").trim_end_matches(".v").to_string(),
+        None => {
+            // The top is the only module with ports that no other module instantiates.
+            let instantiated: HashSet<&str> = modules.values().flat_map(|m| m.submodules.iter().map(|s| s.as_str())).collect();
+            let mut candidates: Vec<&str> = modules.values()
+                .filter(|m| !instantiated.contains(m.name.as_str()) && !m.ports.is_empty())
+                .map(|m| m.name.as_str())
+                .collect();
+            candidates.sort();
+            match candidates.as_slice() {
+                [top] => top.to_string(),
+                [] => anyhow::bail!("Could not find a top module. Pass it with --top."),
+                _ => anyhow::bail!("Found several possible top modules ({}). Pick one with --top.", candidates.join(", ")),
+            }
+        }
+    };
+    anyhow::ensure!(modules.contains_key(toplevel.as_str()), "Module '{}' not found in the project", toplevel);
+
+    // A testbench is a port-less module that instantiates the top.
+    let testbench = modules.values()
+        .filter(|m| m.ports.is_empty() && m.submodules.contains(&toplevel))
+        .map(|m| m.name.clone())
+        .min();
+
+    let files = ordered_files(&toplevel, &design, &HashSet::new());
+    let design_paths: HashSet<PathBuf> = files.iter().cloned().collect();
+    let testbench_files = testbench.as_ref()
+        .map(|tb| ordered_files(tb, &design, &design_paths))
+        .unwrap_or_default();
+
+    let mut defines = Vec::new();
+    let exported: HashSet<PathBuf> = files.iter().filter_map(|f| fs::canonicalize(f).ok()).collect();
+    for entry in read_lockfile()? {
+        if fs::canonicalize(&entry.full_path).is_ok_and(|path| exported.contains(&path)) {
+            for define in entry.defines {
+                push_unique(&mut defines, parse_define(&define));
+            }
+        }
+    }
+
+    let mut include_files = design.include_files(&files, &[]);
+    include_files.retain(|header| !files.contains(header));
+    Ok(ExportedDesign {
+        name: package.name,
+        version: package.version,
+        description: package.description,
+        toplevel,
+        testbench,
+        files: include_files.iter().chain(&files).map(|path| source_file(path)).collect(),
+        testbench_files: testbench_files.iter().map(|path| source_file(path)).collect(),
+        defines,
+    })
+}
+
+/// Orders the files needed by `top`, packages included, so that every file comes after
+/// the files it depends on. Files in `exclude` are left out.
+fn ordered_files(top: &str, design: &DesignIndex, exclude: &HashSet<PathBuf>) -> Vec<PathBuf> {
+    let mut order = design.dependency_files(top);
+    order.retain(|path| !exclude.contains(path));
+    order
+}
+
+fn source_file(path: &Path) -> SourceFile {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    SourceFile {
+        path: path.strip_prefix(".").unwrap_or(path).to_string_lossy().into_owned(),
+        file_type: if extension.starts_with("sv") { "systemVerilogSource" } else { "verilogSource" },
+        is_include_file: extension.ends_with('h'),
+    }
+}
+
+fn fusesoc_core(design: &ExportedDesign) -> Result<String> {
+    let mut core = Mapping::new();
+    core.insert("name".into(), format!("::{}:{}", design.name, design.version).into());
+    core.insert("description".into(), design.description.clone().into());
+
+    let fileset = |files: &[SourceFile]| -> Value {
+        let files: Vec<Value> = files.iter().map(|file| {
+            let mut options = Mapping::new();
+            options.insert("file_type".into(), file.file_type.into());
+            if file.is_include_file {
+                options.insert("is_include_file".into(), true.into());
+            }
+            let mut entry = Mapping::new();
+            entry.insert(file.path.clone().into(), options.into());
+            entry.into()
+        }).collect();
+        let mut fileset = Mapping::new();
+        fileset.insert("files".into(), files.into());
+        fileset.into()
+    };
+    let mut filesets = Mapping::new();
+    filesets.insert("rtl".into(), fileset(&design.files));
+    if !design.testbench_files.is_empty() {
+        filesets.insert("tb".into(), fileset(&design.testbench_files));
+    }
+    core.insert("filesets".into(), filesets.into());
+
+    if !design.defines.is_empty() {
+        let mut parameters = Mapping::new();
+        for (name, value) in &design.defines {
+            let mut parameter = Mapping::new();
+            parameter.insert("datatype".into(), if value.is_some() { "str" } else { "bool" }.into());
+            parameter.insert("paramtype".into(), "vlogdefine".into());
+            parameter.insert("default".into(), value.clone().map_or(Value::Bool(true), Value::from));
+            parameters.insert(name.clone().into(), parameter.into());
+        }
+        core.insert("parameters".into(), parameters.into());
+    }
+
+    let parameter_names: Vec<Value> = design.defines.iter().map(|(name, _)| name.clone().into()).collect();
+    let target = |filesets: Vec<&str>, tool: &str, toplevel: &str| -> Value {
+        let mut target = Mapping::new();
+        target.insert("default_tool".into(), tool.into());
+        target.insert("filesets".into(), filesets.into_iter().map(Value::from).collect::<Vec<_>>().into());
+        if !parameter_names.is_empty() {
+            target.insert("parameters".into(), parameter_names.clone().into());
+        }
+        target.insert("toplevel".into(), toplevel.into());
+        target.into()
+    };
+    let mut targets = Mapping::new();
+    targets.insert("default".into(), {
+        let mut default = Mapping::new();
+        default.insert("filesets".into(), vec![Value::from("rtl")].into());
+        default.into()
+    });
+    match &design.testbench {
+        Some(testbench) => targets.insert("sim".into(), target(vec!["rtl", "tb"], SIM_TOOL, testbench)),
+        None => targets.insert("sim".into(), target(vec!["rtl"], SIM_TOOL, &design.toplevel)),
+    };
+    targets.insert("synth".into(), target(vec!["rtl"], SYNTH_TOOL, &design.toplevel));
+    core.insert("targets".into(), targets.into());
+
+    Ok(format!("CAPI=2:\n{}", serde_yaml::to_string(&core).context("Failed to serialize core file")?))
+}
+
+fn edam(design: &ExportedDesign, target: &str) -> Result<serde_json::Value> {
+    let (tool, toplevel, files): (&str, &str, Vec<&SourceFile>) = match target {
+        "sim" => match &design.testbench {
+            Some(testbench) => (SIM_TOOL, testbench, design.files.iter().chain(&design.testbench_files).collect()),
+            None => (SIM_TOOL, &design.toplevel, design.files.iter().collect()),
+        },
+        "synth" => (SYNTH_TOOL, &design.toplevel, design.files.iter().collect()),
+        other => anyhow::bail!("Unknown target '{}'. Use 'sim' or 'synth'.", other),
+    };
+    let parameters: serde_json::Map<String, serde_json::Value> = design.defines.iter().map(|(name, value)| {
+        let parameter = match value {
+            Some(value) => json!({ "datatype": "str", "paramtype": "vlogdefine", "default": value }),
+            None => json!({ "datatype": "bool", "paramtype": "vlogdefine", "default": true }),
+        };
+        (name.clone(), parameter)
+    }).collect();
+    Ok(json!({
+        "name": format!("{}_{}", design.name, target),
+        "toplevel": toplevel,
+        "files": files.iter().map(|file| {
+            let mut entry = json!({ "name": file.path, "file_type": file.file_type });
+            if file.is_include_file {
+                entry["is_include_file"] = json!(true);
+            }
+            entry
+        }).collect::<Vec<_>>(),
+        "parameters": parameters,
+        "tool_options": { tool: {} },
+    }))
+}
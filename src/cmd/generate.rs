@@ -0,0 +1,154 @@
+use anyhow::{Context, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use crate::cmd::{Execute, Generate};
+use crate::cmd::include::{process_module, IncludeOptions, IncludeRun};
+use crate::design::is_rtl;
+use crate::git::{commit_directory, strip_credentials};
+use crate::toml::{get_generators, set_generator_commit, Generator, CACHE_MARKER};
+use crate::vendor::{copy_dir, fetch_repository, remote_head, VendorArchive};
+
+impl Execute for Generate {
+    async fn execute(&self) -> Result<()> {
+        let generators: Vec<Generator> = get_generators().into_iter()
+            .filter(|g| self.name.as_ref().is_none_or(|name| &g.name == name))
+            .collect();
+        if generators.is_empty() {
+            match &self.name {
+                Some(name) => anyhow::bail!("No generator named '{}' in vpm.toml", name),
+                None => println!("No generators found in vpm.toml. Nothing to generate."),
+            }
+        }
+        for generator in &generators {
+            run_generator(generator, self.force, &IncludeOptions::default())?;
+        }
+        Ok(())
+    }
+}
+
+/// Produces the Verilog of `generator` (or reuses the cached output for the same commit and
+/// parameters) and vendors its top module and submodules like any other dependency, with
+/// `options`.
+pub fn run_generator(generator: &Generator, force: bool, options: &IncludeOptions) -> Result<()> {
+    for (field, value) in [("repo", &generator.repo), ("command", &generator.command), ("output", &generator.output), ("top_module", &generator.top_module)] {
+        anyhow::ensure!(!value.is_empty(), "Generator '{}' is missing '{}' in vpm.toml", generator.name, field);
+    }
+    let commit = if generator.commit.is_empty() {
+        let commit = remote_head(options.archive.as_ref(), &generator.repo)?;
+        set_generator_commit(&generator.name, &commit)?;
+        commit
+    } else {
+        generator.commit.clone()
+    };
+    let key = generator.cache_key(&commit);
+    let cache_dir = crate::config_man::cache_dir()?
+        .join("generated")
+        .join(generator.cache_name(&commit));
+    if force && cache_dir.exists() {
+        fs::remove_dir_all(&cache_dir)?;
+    }
+    if let Some(archive) = &options.archive {
+        let bundled = archive.generated_dir(&generator.cache_name(&commit));
+        if !cache_dir.join(CACHE_MARKER).exists() && bundled.join(CACHE_MARKER).exists() {
+            copy_dir(&bundled, &cache_dir)?;
+        }
+    }
+
+    let output_commit = match fs::read_to_string(cache_dir.join(CACHE_MARKER)) {
+        Ok(output_commit) => {
+            println!("Using cached output of generator '{}' ({})", generator.name, key);
+            output_commit.trim().to_string()
+        }
+        Err(_) => generate(generator, &commit, &cache_dir, options.archive.as_ref())?,
+    };
+
+    let package_name = format!("{}-generated-{}", generator.name, key);
+    let repo_path = PathBuf::from("/tmp").join(&package_name);
+    if repo_path.exists() {
+        fs::remove_dir_all(&repo_path)?;
+    }
+    copy_dir(&cache_dir, &repo_path)?;
+    let source = format!("generator+{}#{}", strip_credentials(&generator.repo), generator.name);
+    let result = process_module(&package_name, &generator.top_module, "./".to_string(), &mut IncludeRun::new(options), &source, None, Some(&output_commit));
+    let _ = fs::remove_dir_all(&repo_path);
+    result?;
+
+    println!("Included generated module '{}' from generator '{}'", generator.top_module, generator.name);
+    Ok(())
+}
+
+/// The environment variable a generator parameter is passed in, e.g. `VPM_PARAM_WIDTH`.
+fn parameter_variable(name: &str) -> String {
+    let name: String = name.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' }).collect();
+    format!("VPM_PARAM_{}", name)
+}
+
+/// Runs the generator in a fresh checkout and stores the Verilog it produces in `cache_dir`.
+fn generate(generator: &Generator, commit: &str, cache_dir: &Path, archive: Option<&VendorArchive>) -> Result<String> {
+    let checkout = tempfile::tempdir()?;
+    let repo_path = checkout.path().join(&generator.name);
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(ProgressStyle::default_spinner().template("{spinner} {msg}").unwrap());
+    pb.set_message(format!("Fetching generator '{}'...", generator.name));
+    pb.enable_steady_tick(Duration::from_millis(100));
+    fetch_repository(archive, &generator.repo, &repo_path, Some(commit), false, Some(&pb))?;
+    pb.finish_and_clear();
+
+    // `{name}` becomes a reference to the parameter's environment variable, so the shell
+    // expands the value after parsing the command and never runs it as code.
+    let mut command_line = generator.command.clone();
+    let mut shown = generator.command.clone();
+    for (name, value) in &generator.parameters {
+        let reference = if cfg!(windows) {
+            format!("\"!{}!\"", parameter_variable(name))
+        } else {
+            format!("\"${}\"", parameter_variable(name))
+        };
+        command_line = command_line.replace(&format!("{{{}}}", name), &reference);
+        shown = shown.replace(&format!("{{{}}}", name), value);
+    }
+    println!("Running generator '{}': {}", generator.name, shown);
+    let start = Instant::now();
+    let mut command = if cfg!(windows) {
+        // Delayed expansion (`!VAR!`) happens after cmd has parsed the line.
+        let mut command = Command::new("cmd");
+        command.args(["/V:ON", "/C"]).arg(&command_line);
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(&command_line);
+        command
+    };
+    for (name, value) in &generator.parameters {
+        command.env(parameter_variable(name), value);
+    }
+    let status = command.current_dir(&repo_path).status()
+        .with_context(|| format!("Failed to run generator '{}'", generator.name))?;
+    anyhow::ensure!(status.success(), "Generator '{}' failed with {}", generator.name, status);
+
+    let output_dir = repo_path.join(&generator.output);
+    anyhow::ensure!(output_dir.is_dir(), "Generator '{}' did not create its output directory '{}'", generator.name, generator.output);
+    if cache_dir.exists() {
+        fs::remove_dir_all(cache_dir)?;
+    }
+    let mut generated = 0;
+    for entry in walkdir::WalkDir::new(&output_dir).into_iter().filter_map(|e| e.ok()).filter(|e| e.file_type().is_file()) {
+        if !is_rtl(entry.path()) {
+            continue;
+        }
+        let target = cache_dir.join(entry.path().strip_prefix(&output_dir)?);
+        fs::create_dir_all(target.parent().unwrap())?;
+        fs::copy(entry.path(), &target)?;
+        generated += 1;
+    }
+    anyhow::ensure!(generated > 0, "Generator '{}' produced no Verilog in '{}'", generator.name, generator.output);
+
+    let output_commit = commit_directory(cache_dir, &format!("Output of '{}' at {}", command_line, commit))?;
+    fs::write(cache_dir.join(CACHE_MARKER), &output_commit)?;
+    println!("Generator '{}' produced {} file(s) in {:.2?}", generator.name, generated, start.elapsed());
+    Ok(output_commit)
+}
@@ -0,0 +1,244 @@
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+
+use crate::cmd::info::find_module;
+use crate::cmd::{Execute, Graph};
+use crate::design::DesignIndex;
+use crate::git::name_from_url;
+use crate::lockfile::{read_lockfile, LockEntry};
+use crate::primitives::primitive_library;
+
+/// What a node of the hierarchy stands for.
+#[derive(Debug, Clone, PartialEq)]
+enum NodeKind {
+    Module,
+    /// A vendor primitive, left as a black box.
+    Primitive,
+    /// Instantiated but declared nowhere in the project.
+    Missing,
+}
+
+#[derive(Debug, Clone)]
+struct GraphNode {
+    name: String,
+    kind: NodeKind,
+    /// The dependency the declaring file was vendored from, or "project".
+    package: String,
+}
+
+/// All instances of one module inside another.
+#[derive(Debug, Clone)]
+struct GraphEdge {
+    parent: usize,
+    child: usize,
+    count: usize,
+    /// Each distinct set of parameter overrides, as `NAME=VALUE` lists.
+    overrides: Vec<String>,
+}
+
+impl Execute for Graph {
+    async fn execute(&self) -> Result<()> {
+        let design = DesignIndex::load();
+        let (_, top) = find_module(&self.top, &design)?;
+        let (nodes, edges) = build_graph(&top.name, &design)?;
+
+        let output = match self.format.as_str() {
+            "dot" => to_dot(&top.name, &nodes, &edges, self.cluster, self.annotate),
+            "mermaid" => to_mermaid(&nodes, &edges, self.cluster, self.annotate),
+            other => anyhow::bail!("Unknown graph format '{}'. Use 'dot' or 'mermaid'.", other),
+        };
+        match &self.output {
+            Some(path) => {
+                fs::write(path, output)?;
+                println!("Wrote the hierarchy of '{}' ({} modules, {} edges) to {}", top.name, nodes.len(), edges.len(), path);
+            }
+            None => print!("{}", output),
+        }
+        Ok(())
+    }
+}
+
+/// Walks the instances below `top` breadth first, visiting each module once.
+fn build_graph(top: &str, design: &DesignIndex) -> Result<(Vec<GraphNode>, Vec<GraphEdge>)> {
+    let lock_entries = read_lockfile()?;
+    let mut nodes: Vec<GraphNode> = Vec::new();
+    let mut edges: Vec<GraphEdge> = Vec::new();
+    let mut queue = VecDeque::new();
+
+    let node_index = |name: &str, nodes: &mut Vec<GraphNode>, queue: &mut VecDeque<usize>| -> usize {
+        if let Some(index) = nodes.iter().position(|n| n.name == name) {
+            return index;
+        }
+        let declaration = design.find_module(name);
+        let (kind, package) = match declaration {
+            Some((path, _)) => (NodeKind::Module, package_of(path, &lock_entries)),
+            None if primitive_library(name).is_some() => (NodeKind::Primitive, primitive_library(name).unwrap_or_default().to_string()),
+            None => (NodeKind::Missing, String::new()),
+        };
+        nodes.push(GraphNode { name: name.to_string(), kind, package });
+        if declaration.is_some() {
+            queue.push_back(nodes.len() - 1);
+        }
+        nodes.len() - 1
+    };
+
+    node_index(top, &mut nodes, &mut queue);
+    while let Some(parent) = queue.pop_front() {
+        let Some((_, interface)) = design.find_module(&nodes[parent].name) else { continue };
+        for instance in &interface.instances {
+            if instance.module.starts_with('`') {
+                continue;
+            }
+            let child = node_index(&instance.module, &mut nodes, &mut queue);
+            let parameter_names: Vec<String> = design.find_module(&instance.module)
+                .map(|(_, child)| child.parameters.iter().map(|(name, _)| name.clone()).collect())
+                .unwrap_or_default();
+            let overrides = instance.parameters.iter().enumerate()
+                .map(|(position, (name, value))| {
+                    let name = name.clone().or_else(|| parameter_names.get(position).cloned()).unwrap_or_else(|| format!("#{}", position));
+                    format!("{}={}", name, value)
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            match edges.iter_mut().find(|e| e.parent == parent && e.child == child) {
+                Some(edge) => {
+                    edge.count += 1;
+                    if !overrides.is_empty() && !edge.overrides.contains(&overrides) {
+                        edge.overrides.push(overrides);
+                    }
+                }
+                None => edges.push(GraphEdge {
+                    parent,
+                    child,
+                    count: 1,
+                    overrides: if overrides.is_empty() { Vec::new() } else { vec![overrides] },
+                }),
+            }
+        }
+    }
+    Ok((nodes, edges))
+}
+
+/// Names the dependency `path` was vendored from, using the lockfile entry that covers it.
+fn package_of(path: &Path, lock_entries: &[LockEntry]) -> String {
+    let Ok(canonical) = fs::canonicalize(path) else { return "project".to_string() };
+    lock_entries.iter()
+        .find(|entry| fs::canonicalize(&entry.full_path).is_ok_and(|locked| canonical == locked))
+        .map(|entry| name_from_url(entry.source.split('#').next().unwrap_or_default()).trim_end_matches(".git").to_string())
+        .or_else(|| {
+            // Registry packages are unpacked into vpm_modules/<name> without per-file entries.
+            let mut rest = path.components().skip_while(|c| c.as_os_str() != "vpm_modules").skip(1);
+            let package = rest.next()?;
+            rest.next().map(|_| package.as_os_str().to_string_lossy().to_string())
+        })
+        .unwrap_or_else(|| "project".to_string())
+}
+
+/// The edge annotation: instance count and parameter overrides, one per line.
+fn edge_label(edge: &GraphEdge) -> Vec<String> {
+    let mut lines = Vec::new();
+    if edge.count > 1 {
+        lines.push(format!("{}×", edge.count));
+    }
+    lines.extend(edge.overrides.iter().cloned());
+    lines
+}
+
+/// Packages in order of first appearance, each with the nodes that belong to it.
+fn clusters(nodes: &[GraphNode]) -> Vec<(String, Vec<usize>)> {
+    let mut clusters: Vec<(String, Vec<usize>)> = Vec::new();
+    for (index, node) in nodes.iter().enumerate().filter(|(_, n)| !n.package.is_empty()) {
+        match clusters.iter_mut().find(|(package, _)| *package == node.package) {
+            Some((_, members)) => members.push(index),
+            None => clusters.push((node.package.clone(), vec![index])),
+        }
+    }
+    clusters
+}
+
+fn to_dot(top: &str, nodes: &[GraphNode], edges: &[GraphEdge], cluster: bool, annotate: bool) -> String {
+    let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"));
+    let mut dot = format!("digraph {} {{\n    rankdir=TB;\n    node [shape=box, fontname=\"Helvetica\"];\n", quote(top));
+    let node_line = |node: &GraphNode| match node.kind {
+        NodeKind::Module => quote(&node.name),
+        NodeKind::Primitive => format!("{} [style=dashed, label={}]", quote(&node.name), quote(&format!("{}\n({} black box)", node.name, node.package))),
+        NodeKind::Missing => format!("{} [style=dotted, color=red, label={}]", quote(&node.name), quote(&format!("{}\n(not found)", node.name))),
+    };
+
+    if cluster {
+        for (index, (package, members)) in clusters(nodes).iter().enumerate() {
+            dot.push_str(&format!("    subgraph cluster_{} {{\n        label={};\n        style=rounded;\n", index, quote(package)));
+            for &member in members {
+                dot.push_str(&format!("        {};\n", node_line(&nodes[member])));
+            }
+            dot.push_str("    }\n");
+        }
+        for node in nodes.iter().filter(|n| n.package.is_empty()) {
+            dot.push_str(&format!("    {};\n", node_line(node)));
+        }
+    } else {
+        for node in nodes {
+            dot.push_str(&format!("    {};\n", node_line(node)));
+        }
+    }
+
+    for edge in edges {
+        let label = edge_label(edge);
+        let attributes = if annotate && !label.is_empty() {
+            format!(" [label={}]", quote(&label.join("\n")))
+        } else {
+            String::new()
+        };
+        dot.push_str(&format!("    {} -> {}{};\n", quote(&nodes[edge.parent].name), quote(&nodes[edge.child].name), attributes));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+fn to_mermaid(nodes: &[GraphNode], edges: &[GraphEdge], cluster: bool, annotate: bool) -> String {
+    // Mermaid labels cannot contain double quotes.
+    let label = |text: &str| text.replace('"', "'");
+    let node_line = |index: usize| {
+        let node = &nodes[index];
+        match node.kind {
+            NodeKind::Module => format!("n{}[\"{}\"]", index, label(&node.name)),
+            NodeKind::Primitive => format!("n{}[/\"{}<br/>({} black box)\"/]", index, label(&node.name), node.package),
+            NodeKind::Missing => format!("n{}[\"{}<br/>(not found)\"]", index, label(&node.name)),
+        }
+    };
+    let mut mermaid = String::from("flowchart TD\n");
+
+    if cluster {
+        for (index, (package, members)) in clusters(nodes).iter().enumerate() {
+            mermaid.push_str(&format!("    subgraph package{}[\"{}\"]\n", index, label(package)));
+            for &member in members {
+                mermaid.push_str(&format!("        {}\n", node_line(member)));
+            }
+            mermaid.push_str("    end\n");
+        }
+        for (index, _) in nodes.iter().enumerate().filter(|(_, n)| n.package.is_empty()) {
+            mermaid.push_str(&format!("    {}\n", node_line(index)));
+        }
+    } else {
+        for index in 0..nodes.len() {
+            mermaid.push_str(&format!("    {}\n", node_line(index)));
+        }
+    }
+
+    for edge in edges {
+        let annotation = edge_label(edge);
+        if annotate && !annotation.is_empty() {
+            mermaid.push_str(&format!("    n{} -->|\"{}\"| n{}\n", edge.parent, label(&annotation.join("<br/>")), edge.child));
+        } else {
+            mermaid.push_str(&format!("    n{} --> n{}\n", edge.parent, edge.child));
+        }
+    }
+    let missing: Vec<String> = nodes.iter().enumerate().filter(|(_, n)| n.kind == NodeKind::Missing).map(|(i, _)| format!("n{}", i)).collect();
+    if !missing.is_empty() {
+        mermaid.push_str(&format!("    classDef missing stroke:#d00,stroke-dasharray:3 3\n    class {} missing\n", missing.join(",")));
+    }
+    mermaid
+}
+
@@ -1,121 +1,175 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
 use std::env::current_dir;
-use std::path::{Path, PathBuf};
-use std::{fs, process::Command};
+use std::path::{Component, Path, PathBuf};
+use std::fs;
 use anyhow::{Context, Result};
 use once_cell::sync::Lazy;
-use tree_sitter::{Node, Parser, Query, QueryCursor};
 use crate::cmd::{Execute, Include};
-use crate::toml::{add_dependency, add_top_module};
+use crate::config_man::get_registry_index;
+use crate::git::{list_repo_files, materialize_files, name_from_url, strip_credentials};
+use crate::registry::{fetch_archive, install_package, resolve, sha256_checksum};
+use crate::toml::{add_dependency, add_registry_dependency, add_top_module, get_search_paths};
+use crate::vendor::{fetch_repository, open_archive, remote_head, VendorArchive};
+use crate::manifest::{find_manifests, resolve_module, ResolvedManifest};
+use crate::design::{get_submodules, is_source, parse_file_with_defines, source_dependencies, DesignIndex};
+use crate::lockfile::{edit_lockfile, parse_lockfile, read_lockfile};
+use crate::preprocess::{configure_defines, parse_define, DefineSet};
+use crate::primitives::{primitive_library, split_primitives};
+use crate::stubs::{write_stubs, StubKinds};
 use walkdir::{DirEntry, WalkDir};
-use fancy_regex::Regex;
 
-use dialoguer::{theme::ColorfulTheme, MultiSelect};
+use dialoguer::{theme::ColorfulTheme, MultiSelect, Select};
 use fuzzy_matcher::FuzzyMatcher;
 use fuzzy_matcher::skim::SkimMatcherV2;
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Write};
+use std::time::Instant;
 use indicatif::{ProgressBar, ProgressStyle};
 
 impl Execute for Include {
     async fn execute(&self) -> Result<()> {
+        let options = IncludeOptions {
+            archive: self.archive.as_ref().map(|archive| open_archive(Path::new(archive))).transpose()?,
+            defines: (!self.define.is_empty())
+                .then(|| configure_defines(&self.define.iter().map(|d| parse_define(d)).collect::<Vec<_>>())),
+            stubs: StubKinds { blackbox: self.blackbox, template: self.template, json: self.interface_json },
+        };
         println!("Including from: '{}'", self.url);
+        if is_registry_spec(&self.url) {
+            return include_from_registry(&self.url, &options).await;
+        }
         let repo_name = name_from_url(&self.url);
         let tmp_path = PathBuf::from("/tmp").join(repo_name);
         let commit = if self.commit.is_none() {
-            Some(get_head_commit_hash(&self.url)?)
+            Some(get_head_commit_hash(&self.url, options.archive.as_ref())?)
         } else {
             self.commit.clone()
         };
         if self.repo {
-            include_entire_repo(&self.url, &tmp_path, self.riscv, commit.as_deref())?
+            include_entire_repo(&self.url, &tmp_path, self.riscv, commit.as_deref(), &options)?
         } else {
-            include_single_module(&self.url, self.riscv, commit.as_deref())?
+            include_single_module(&self.url, self.riscv, commit.as_deref(), &options)?
         }
         Ok(())
     }
 }
 
-pub fn get_head_commit_hash(url: &str) -> Result<String> {
-    let github_url = if url.starts_with("https://github.com/") {
-        url.to_string()
-    } else {
-        format!("https://github.com/{}", url)
-    };
+/// Settings of one `vpm include` or `vpm sync` that apply to every module it vendors.
+#[derive(Debug, Default)]
+pub struct IncludeOptions {
+    /// Vendor archive to take repositories and registry packages from instead of the network.
+    pub archive: Option<VendorArchive>,
+    /// Defines to resolve dependencies with. Without them, sources are analysed as written
+    /// and every conditional branch counts.
+    pub defines: Option<DefineSet>,
+    /// Artifacts to generate in `headers/` for each vendored file.
+    pub stubs: StubKinds,
+}
 
-    let (repo_url, _) = github_url.rsplit_once("/blob/").unwrap_or((&github_url, ""));
+/// One module being vendored: the options it is vendored with and the files vendored so far.
+pub struct IncludeRun<'a> {
+    pub options: &'a IncludeOptions,
+    pub visited: HashSet<String>,
+}
+
+impl<'a> IncludeRun<'a> {
+    pub fn new(options: &'a IncludeOptions) -> Self {
+        IncludeRun { options, visited: HashSet::new() }
+    }
+}
 
-    let output = Command::new("git")
-        .args(["ls-remote", repo_url, "HEAD"])
-        .output()?;
+/// Prefix that marks a registry package when no version is given ('registry:NAME').
+const REGISTRY_PREFIX: &str = "registry:";
+
+/// Whether `url` names a registry package ('NAME@VERSION' or 'registry:NAME[@VERSION]')
+/// rather than a repository. A bare name is not enough, since it may be a typo'd path.
+fn is_registry_spec(url: &str) -> bool {
+    let spec = url.strip_prefix(REGISTRY_PREFIX).unwrap_or(url);
+    let is_name = |name: &str| !name.is_empty() && !name.contains(['/', '\\', ':', '@']);
+    match spec.split_once('@') {
+        Some((name, version)) => is_name(name) && !version.is_empty() && !version.contains(['/', ':']),
+        None => url.starts_with(REGISTRY_PREFIX) && is_name(spec),
+    }
+}
 
-    if output.status.success() {
-        let stdout = String::from_utf8(output.stdout)?;
-        let hash = stdout.split_whitespace().next().unwrap_or("").to_string();
-        if !hash.is_empty() {
-            Ok(hash[..7].to_string())  // Return only the first 7 characters (short hash)
+/// Installs a package resolved through the registry index into `vpm_modules/<name>`.
+pub async fn include_from_registry(spec: &str, options: &IncludeOptions) -> Result<()> {
+    let spec = spec.strip_prefix(REGISTRY_PREFIX).unwrap_or(spec);
+    let (name, version) = match spec.split_once('@') {
+        Some((name, version)) => (name, Some(version)),
+        None => (spec, None),
+    };
+    let (index_dir, package) = resolve(name, version, options.archive.as_ref())?;
+    println!("Resolved {}@{} from registry index '{}'", package.name, package.version, index_dir.display());
+
+    let archive = fetch_archive(&index_dir, &package).await?;
+    let destination = PathBuf::from("vpm_modules").join(&package.name);
+    install_package(&package, &archive, &destination)?;
+
+    add_registry_dependency(&package.name, &package.version, &package.top_modules)?;
+    // Packages installed from a vendor archive keep the index they were originally resolved from.
+    let registry_source = options.archive.as_ref().map_or_else(get_registry_index, |archive| archive.registry_index.clone());
+    edit_lockfile(|lockfile| {
+        let entry = format!(
+            "\n[[package]]\nfull_path = \"{}\"\nsource = \"registry+{}#{}@{}\"\nchecksum = \"{}\"\n",
+            destination.display(), registry_source, package.name, package.version, package.checksum
+        );
+        let marker = format!("\n[[package]]\nfull_path = \"{}\"\n", destination.display());
+        if let Some(start) = lockfile.find(&marker) {
+            let end = lockfile[start + 1..].find("\n[[").map(|i| start + 1 + i).unwrap_or(lockfile.len());
+            lockfile.replace_range(start..end, &entry);
         } else {
-            Err(anyhow::anyhow!("Failed to get HEAD commit hash: Empty hash returned"))
+            lockfile.push_str(&entry);
         }
+        Ok(())
+    })?;
+
+    println!("Successfully installed package {}@{} into {}", package.name, package.version, destination.display());
+    Ok(())
+}
+
+/// The commit to include `url` at when none is given: its remote HEAD, or the commit it is
+/// bundled at in `archive`.
+pub fn get_head_commit_hash(url: &str, archive: Option<&VendorArchive>) -> Result<String> {
+    let github_url = if is_remote_url(url) {
+        url.to_string()
     } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        Err(anyhow::anyhow!("Failed to get HEAD commit hash: {}", stderr))
-    }
+        format!("https://github.com/{}", url)
+    };
+
+    let repo_url = split_file_url(&github_url).map_or(github_url, |(repo_url, _)| repo_url);
+
+    remote_head(archive, &repo_url)
 }
 
-fn include_entire_repo(url: &str, tmp_path: &PathBuf, riscv: bool, commit_hash: Option<&str>) -> Result<()> {
-    let url = format!("https://github.com/{}", url);
+fn include_entire_repo(url: &str, tmp_path: &PathBuf, riscv: bool, commit_hash: Option<&str>, options: &IncludeOptions) -> Result<()> {
+    let url = if is_remote_url(url) { url.to_string() } else { format!("https://github.com/{}", url) };
     println!("Full GitHub URL: {}@{}", url, commit_hash.unwrap_or("HEAD"));
-    include_repo_from_url(&url, "/tmp/", commit_hash)?;
+    let commit = include_repo_from_url(&url, "/tmp/", commit_hash, true, options.archive.as_ref())?;
     add_dependency(&url)?;
 
-    let files = get_files(&tmp_path.to_str().unwrap_or_default());
-    let items = get_relative_paths(&files, tmp_path);
+    let items = list_repo_files(tmp_path)?;
 
     let selected_items = select_modules(&items)?;
 
-    process_selected_modules(&url, tmp_path, &selected_items, riscv, commit_hash)?;
+    process_selected_modules(&url, tmp_path, &selected_items, riscv, &commit, options)?;
 
     fs::remove_dir_all(tmp_path)?;
     print_success_message(&url, &selected_items);
     Ok(())
 }
 
-fn include_single_module(url: &str, riscv: bool, commit_hash: Option<&str>) -> Result<()> {
-    let repo_url = get_github_repo_url(url).unwrap();
-    include_repo_from_url(&repo_url, "/tmp/", commit_hash)?;
+fn include_single_module(url: &str, riscv: bool, commit_hash: Option<&str>, options: &IncludeOptions) -> Result<()> {
+    let (repo_url, module_path) = split_file_url(url)
+        .with_context(|| format!("'{}' does not point to a file in a repository. Use --repo to include from a whole repository.", url))?;
     add_dependency(&repo_url)?;
     println!("Repo URL: {}@{}", repo_url, commit_hash.unwrap_or("HEAD"));
-    let module_path = get_component_path_from_github_url(url).unwrap_or_default();
     println!("Including module: {}", module_path);
-    include_module_from_url(&module_path, &repo_url, riscv, commit_hash)?;
+    include_module_from_url(&module_path, &repo_url, riscv, commit_hash, options)?;
     println!("Successfully installed module: {}", module_path);
     Ok(())
 }
 
-fn get_files(directory: &str) -> Vec<String> {
-    WalkDir::new(directory)
-        .into_iter()
-        .filter_map(|entry| {
-            entry.ok().and_then(|e| {
-                if e.file_type().is_file() {
-                    Some(e.path().to_string_lossy().into_owned())
-                } else {
-                    None
-                }
-            })
-        })
-        .collect()
-}
-
-fn get_relative_paths(files: &[String], tmp_path: &PathBuf) -> Vec<String> {
-    files.iter()
-        .map(|file| file.strip_prefix(&tmp_path.to_string_lossy().as_ref())
-            .unwrap_or(file)
-            .trim_start_matches('/')
-            .to_string())
-        .collect()
-}
-
 fn select_modules(items: &[String]) -> Result<HashSet<String>> {
     let matcher = SkimMatcherV2::default();
     let mut selected_items: HashSet<String> = HashSet::new();
@@ -154,8 +208,12 @@ fn select_modules(items: &[String]) -> Result<HashSet<String>> {
     Ok(selected_items)
 }
 
-fn process_selected_modules(url: &str, tmp_path: &PathBuf, selected_items: &HashSet<String>, riscv: bool, commit_hash: Option<&str>) -> Result<()> {
-    for item in selected_items {
+fn process_selected_modules(url: &str, tmp_path: &PathBuf, selected_items: &HashSet<String>, riscv: bool, commit: &str, options: &IncludeOptions) -> Result<()> {
+    // The repository is already fetched at `commit`, so modules are vendored from the
+    // same checkout in a stable order rather than re-cloning for each selection.
+    let mut selected_items: Vec<&String> = selected_items.iter().collect();
+    selected_items.sort();
+    for item in &selected_items {
         let displayed_path = item.strip_prefix(tmp_path.to_string_lossy().as_ref()).unwrap_or(item).trim_start_matches('/');
         println!("Including module: {}", displayed_path);
         
@@ -163,12 +221,12 @@ fn process_selected_modules(url: &str, tmp_path: &PathBuf, selected_items: &Hash
         let module_path = full_path.strip_prefix(tmp_path).unwrap_or(&full_path).to_str().unwrap().trim_start_matches('/');
         println!("Module path: {}", module_path);
 
-        include_module_from_url(module_path, url, riscv, commit_hash)?;
+        include_module_from_repo(name_from_url(url), module_path, url, riscv, commit, options)?;
     }
 
     if selected_items.is_empty() {
         println!("No modules selected. Including entire repository.");
-        include_repo_from_url(url, "./", commit_hash)?;
+        include_repo_from_url(url, "./", Some(commit), false, options.archive.as_ref())?;
     }
 
     Ok(())
@@ -186,26 +244,22 @@ fn print_success_message(url: &str, selected_items: &HashSet<String>) {
     }
 }
 
-fn name_from_url(url: &str) -> &str {
-    url.rsplit('/').find(|&s| !s.is_empty()).unwrap_or_default()
-}
 
-fn get_component_path_from_github_url(url: &str) -> Option<String> {
-    let parts: Vec<&str> = url.split("/").collect();
-    if parts.len() < 8 || !url.starts_with("https://github.com/") {
-        return None;
-    }
-
-    Some(parts[7..].join("/"))
+/// Whether `url` is a full repository URL (any host, HTTPS or SSH) rather than 'AUTHOR_NAME/REPO_NAME'.
+fn is_remote_url(url: &str) -> bool {
+    url.contains("://") || url.starts_with("git@")
 }
 
-fn get_github_repo_url(url: &str) -> Option<String> {
-    let parts: Vec<&str> = url.split('/').collect();
-    if parts.len() < 5 || !url.starts_with("https://github.com/") {
-        return None;
-    }
-
-    Some(format!("https://github.com/{}/{}", parts[3], parts[4]))
+/// Splits the URL of a file in a hosted repository into the repository URL and the
+/// file's path in it. Understands GitHub (`/blob/<ref>/`), GitLab (`/-/blob/<ref>/`) and
+/// Gitea or Forgejo (`/src/<branch|tag|commit>/<ref>/`) links on any host; URLs without
+/// a scheme are taken to be on GitHub.
+fn split_file_url(url: &str) -> Option<(String, String)> {
+    let url = if is_remote_url(url) { url.to_string() } else { format!("https://github.com/{}", url) };
+    let (repo_url, rest) = ["/-/blob/", "/blob/", "/src/branch/", "/src/tag/", "/src/commit/"].iter()
+        .find_map(|marker| url.split_once(marker))?;
+    let (_, path) = rest.split_once('/')?;
+    (!path.is_empty()).then(|| (repo_url.trim_end_matches(".git").to_string(), path.to_string()))
 }
 
 fn is_full_filepath(path: &str) -> bool {
@@ -339,15 +393,24 @@ fn generate_xdc_content(module_path: &str) -> Result<String> {
     Ok(xdc_content)
 }
 
-pub fn include_module_from_url(module_path: &str, url: &str, riscv: bool, commit_hash: Option<&str>) -> Result<()> {
-    let package_name = name_from_url(url);
+pub fn include_module_from_url(module_path: &str, url: &str, riscv: bool, commit_hash: Option<&str>, options: &IncludeOptions) -> Result<()> {
+    let commit = include_repo_from_url(url, "/tmp/", commit_hash, true, options.archive.as_ref())?;
+    include_module_from_repo(name_from_url(url), module_path, url, riscv, &commit, options)
+}
 
-    include_repo_from_url(url, "/tmp/", commit_hash)?;
+/// Vendors `module_path` and its submodules from a repository that has already been
+/// fetched into `/tmp/<package_name>` at `commit`.
+pub fn include_module_from_repo(package_name: &str, module_path: &str, url: &str, riscv: bool, commit: &str, options: &IncludeOptions) -> Result<()> {
     let destination = "./";
-    process_module(package_name, module_path, destination.to_owned(), &mut HashSet::new(), url, true, commit_hash)?;
+    process_module(package_name, module_path, destination.to_owned(), &mut IncludeRun::new(options), url, None, Some(commit))?;
 
-    let module_path = Path::new(&destination).join(Path::new(module_path).file_name().unwrap());
-    anyhow::ensure!(module_path.exists(), "Module file not found in the destination folder");
+    // Files selected by a manifest keep their directory in the repository.
+    let file_name = Path::new(module_path).file_name().unwrap();
+    let source = strip_credentials(url);
+    let module_path = std::iter::once(Path::new(&destination).join(file_name))
+        .chain(read_lockfile()?.into_iter().filter(|entry| entry.source == source).map(|entry| PathBuf::from(entry.full_path)))
+        .find(|path| path.file_name() == Some(file_name) && path.exists())
+        .context("Module file not found in the destination folder")?;
 
     if riscv {
         let top_v_content = generate_top_v_content(&module_path.to_str().unwrap())?;
@@ -358,20 +421,23 @@ pub fn include_module_from_url(module_path: &str, url: &str, riscv: bool, commit
         fs::write(format!("{}/constraints.xdc", destination), xdc_content)?;
         println!("Created constraints.xdc file for Xilinx Artix-7 board in {}", destination);
     }
-    add_top_module(url, current_dir()?.join(module_path.file_name().unwrap()).to_str().unwrap(), commit_hash.unwrap_or(""))?;
+    add_top_module(url, current_dir()?.join(module_path.file_name().unwrap()).to_str().unwrap(), commit)?;
     
     Ok(())
 }
 
-pub fn process_module(package_name: &str, module: &str, destination: String, visited: &mut HashSet<String>, url: &str, is_top_module: bool, commit_hash: Option<&str>) -> Result<HashSet<String>> {
+/// Vendors `module` and everything below it into `destination`. `parent` is the vendored
+/// file that instantiates the module, or `None` for a top module.
+pub fn process_module(package_name: &str, module: &str, destination: String, run: &mut IncludeRun, url: &str, parent: Option<&Path>, commit_hash: Option<&str>) -> Result<HashSet<String>> {
     // println!("Processing module: {}", module);
+    let is_top_module = parent.is_none();
     let module_name = module.strip_suffix(".v").or_else(|| module.strip_suffix(".sv")).unwrap_or(module);
     let module_with_ext = if module.ends_with(".v") || module.ends_with(".sv") {
         module.to_string()
     } else {
         format!("{}.v", module_name)
     };
-    if !visited.insert(module_with_ext.clone()) {
+    if !run.visited.insert(module_with_ext.clone()) {
         return Ok(HashSet::new());
     }
 
@@ -380,86 +446,292 @@ pub fn process_module(package_name: &str, module: &str, destination: String, vis
 
     let target_path = PathBuf::from(&destination);
 
+    // FuseSoC and Bender manifests list a core's files exactly, so prefer them over
+    // guessing the submodules from the sources.
+    if is_top_module {
+        let manifests = find_manifests(&tmp_path)?;
+        let resolved = resolve_module(&manifests, &module_with_ext)
+            .or_else(|| resolve_module(&manifests, &format!("{}.sv", module_name)));
+        if let Some(resolved) = resolved {
+            return include_from_manifest(&resolved, &tmp_path, &target_path, module_name, url, run);
+        }
+    }
+
     println!("Including submodule '{}'", module_with_ext);
 
     let mut processed_modules = HashSet::new();
 
-    if is_full_filepath(&module_with_ext) {
+    let vendored_file = if is_full_filepath(&module_with_ext) {
         // println!("Full filepath detected for module '{}'", module_with_ext);
+        materialize_files(&tmp_path, std::slice::from_ref(&file_path))?;
         let dir_entry = filepath_to_dir_entry(file_path)?;
-        process_file(&dir_entry, &target_path.to_str().unwrap(), module, url, visited, is_top_module)?;
+        process_file(&dir_entry, &target_path.to_str().unwrap(), module, url, run, is_top_module)?;
         processed_modules.insert(module_with_ext.clone());
+        edit_lockfile(|lockfile| {
+            set_lock_field(lockfile, &target_path.join(Path::new(module).file_name().unwrap()), "origin", &format!("\"{}\"", module));
+            Ok(())
+        })?;
+        module.to_string()
     } else {
         // println!("Full filepath not detected for module '{}'", module_with_ext);
-        process_non_full_filepath(module_name, &tmp_path, &target_path, url, visited, is_top_module, &mut processed_modules)?;
-    }
+        match process_non_full_filepath(module_name, &tmp_path, &target_path, url, run, parent, &mut processed_modules)? {
+            Some(file_name) => file_name,
+            // The file declaring the module was vendored already, along with its submodules.
+            None => return Ok(processed_modules),
+        }
+    };
 
-    let submodules = download_and_process_submodules(package_name, module, &destination, url, visited, is_top_module, commit_hash)?;
+    let submodules = download_and_process_submodules(package_name, &vendored_file, &destination, url, run, is_top_module, commit_hash)?;
     processed_modules.extend(submodules);
 
     Ok(processed_modules)
 }
 
-fn process_non_full_filepath(module_name: &str, tmp_path: &PathBuf, target_path: &PathBuf, url: &str, visited: &mut HashSet<String>, is_top_module: bool, processed_modules: &mut HashSet<String>) -> Result<()> {
-    let matching_entries = find_matching_entries(module_name, tmp_path);
+/// Vendors every file listed by the manifests in `resolved` and records the include
+/// directories and defines they declare on the top module's lockfile entry.
+fn include_from_manifest(resolved: &ResolvedManifest, tmp_path: &Path, target_path: &Path, module_name: &str, url: &str, run: &mut IncludeRun) -> Result<HashSet<String>> {
+    println!("Selecting files from {}", resolved.manifests.join(", "));
+    let headers: Vec<String> = list_repo_files(tmp_path)?.into_iter()
+        .filter(|file| resolved.include_dirs.iter().any(|dir| Path::new(file).parent() == Some(Path::new(dir))))
+        .filter(|file| file.ends_with(".vh") || file.ends_with(".svh") || file.ends_with(".svi"))
+        .filter(|file| !resolved.files.contains(file))
+        .collect();
+    let files: Vec<PathBuf> = resolved.files.iter().chain(&headers).map(|file| tmp_path.join(file)).collect();
+    materialize_files(tmp_path, &files)?;
+
+    // Files keep their place in the repository, so relative includes and files with the
+    // same name in different directories still work.
+    let mut processed_modules = HashSet::new();
+    let mut top_file = None;
+    for file in &files {
+        let relative = file.strip_prefix(tmp_path).unwrap_or(file);
+        let file_name = file.file_name().and_then(|f| f.to_str()).unwrap_or_default().to_string();
+        let destination = target_path.join(relative.parent().unwrap_or(Path::new("")));
+        fs::create_dir_all(&destination)?;
+        let is_rtl = file_name.ends_with(".v") || file_name.ends_with(".sv");
+        if !is_rtl {
+            fs::copy(file, destination.join(&file_name))?;
+            continue;
+        }
+        let is_top_module = file.file_stem() == Path::new(module_name).file_name();
+        if is_top_module {
+            top_file = Some(destination.join(&file_name));
+        }
+        println!("Including file '{}'", relative.display());
+        run.visited.insert(file_name.clone());
+        process_file(&filepath_to_dir_entry(file.clone())?, destination.to_str().unwrap(), &file_name, url, run, is_top_module)?;
+        processed_modules.insert(file_name);
+    }
+
+    if let Some(top_file) = top_file {
+        let quoted = |items: Vec<String>| format!("[{}]", items.iter().map(|i| format!("\"{}\"", i)).collect::<Vec<_>>().join(", "));
+        let include_dirs = resolved.include_dirs.iter().map(|dir| target_path.join(dir).display().to_string()).collect();
+        edit_lockfile(|lockfile| {
+            set_lock_field(lockfile, &top_file, "manifests", &quoted(resolved.manifests.clone()));
+            set_lock_field(lockfile, &top_file, "include_dirs", &quoted(include_dirs));
+            set_lock_field(lockfile, &top_file, "defines", &quoted(resolved.defines.iter()
+                .map(|(name, value)| value.as_ref().map_or_else(|| name.clone(), |value| format!("{}={}", name, value)))
+                .collect()));
+            Ok(())
+        })?;
+    }
+    for dependency in &resolved.external_dependencies {
+        println!(
+            "Note: '{}' depends on '{}'{}, which is not part of this repository. Include it separately.",
+            module_name, dependency.name, dependency.source.as_ref().map(|s| format!(" ({})", s)).unwrap_or_default()
+        );
+    }
+    Ok(processed_modules)
+}
+
+/// Vendors the file declaring `module_name` and records the module and where it came from
+/// on its lockfile entry. Returns the name of the vendored file, or `None` when there was
+/// nothing new to vendor.
+fn process_non_full_filepath(module_name: &str, tmp_path: &PathBuf, target_path: &PathBuf, url: &str, run: &mut IncludeRun, parent: Option<&Path>, processed_modules: &mut HashSet<String>) -> Result<Option<String>> {
+    let is_top_module = parent.is_none();
+    let matching_entries = find_declaring_files(module_name, tmp_path, run.options.defines.as_ref())?;
     println!("Found {} matching entries for module '{}'", matching_entries.len(), module_name);
-    if matching_entries.is_empty() {
-        println!("No matching files found for module '{}'. Skipping...", module_name);
-    } else if matching_entries.len() == 1 {
-        let dir_entry = filepath_to_dir_entry(matching_entries[0].clone())?;
-        process_file(&dir_entry, target_path.to_str().unwrap(), module_name, url, visited, is_top_module)?;
-        processed_modules.insert(format!("{}.v", module_name));
-    } else {
-        process_multiple_matches(matching_entries, target_path, module_name, url, visited, is_top_module, processed_modules)?;
+    let entry = match matching_entries.len() {
+        0 => {
+            println!("No matching files found for module '{}'. Skipping...", module_name);
+            return Ok(None);
+        }
+        1 => matching_entries[0].clone(),
+        _ => choose_match(matching_entries, module_name, tmp_path, target_path, parent)?,
+    };
+    let origin = entry.strip_prefix(tmp_path).unwrap_or(&entry).to_string_lossy().replace('\\', "/");
+
+    let file_name = entry.file_name().and_then(|f| f.to_str()).unwrap_or_default().to_string();
+    let vendored_path = target_path.join(&file_name);
+    // A file declaring several modules is vendored once, for the first of them that is used.
+    let first_visit = run.visited.insert(file_name.clone());
+    let is_new = first_visit || Path::new(&file_name).file_stem().and_then(|s| s.to_str()) == Some(module_name);
+    if is_new {
+        if file_name != format!("{}.v", module_name) && file_name != format!("{}.sv", module_name) {
+            println!("Module '{}' is declared in '{}'", module_name, file_name);
+        }
+        process_file(&filepath_to_dir_entry(entry)?, target_path.to_str().unwrap(), &file_name, url, run, is_top_module)?;
+        processed_modules.insert(file_name.clone());
     }
+    record_module(&vendored_path, module_name)?;
+    edit_lockfile(|lockfile| {
+        set_lock_field(lockfile, &vendored_path, "origin", &format!("\"{}\"", origin));
+        Ok(())
+    })?;
 
-    Ok(())
+    Ok(is_new.then_some(file_name))
+}
+
+/// Finds the repository files that declare `module_name`. Files named after the module are
+/// checked first; otherwise every source in the repository is parsed.
+fn find_declaring_files(module_name: &str, tmp_path: &PathBuf, defines: Option<&DefineSet>) -> Result<Vec<PathBuf>> {
+    let named = find_matching_entries(module_name, tmp_path)?;
+    materialize_files(tmp_path, &named)?;
+    let declaring: Vec<PathBuf> = named.iter()
+        .filter(|path| parse_file_with_defines(path, defines).is_ok_and(|modules| modules.iter().any(|m| m.name == module_name)))
+        .cloned()
+        .collect();
+    if !declaring.is_empty() {
+        return Ok(declaring);
+    }
+    let declaring = repo_module_index(tmp_path, defines)?.get(module_name).cloned().unwrap_or_default();
+    // Files that could not be parsed still match by name.
+    Ok(if declaring.is_empty() { named } else { declaring })
 }
 
-fn find_matching_entries(module_name: &str, tmp_path: &PathBuf) -> Vec<PathBuf> {
-    WalkDir::new(tmp_path)
+/// Directories whose sources are usually testbenches or examples rather than the design.
+const NON_DESIGN_DIRS: &[&str] = &["test", "tests", "tb", "testbench", "bench", "sim", "simulation", "verif", "dv", "example", "examples"];
+
+/// Module names and the files declaring them.
+type ModuleFiles = HashMap<String, Vec<PathBuf>>;
+
+/// Module name to declaring files for every source of a fetched repository, built once per
+/// repository and process.
+fn repo_module_index(tmp_path: &Path, defines: Option<&DefineSet>) -> Result<ModuleFiles> {
+    static INDEXES: Lazy<Mutex<HashMap<PathBuf, ModuleFiles>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+    if let Some(index) = INDEXES.lock().unwrap().get(tmp_path) {
+        return Ok(index.clone());
+    }
+
+    let sources: Vec<PathBuf> = list_repo_files(tmp_path)?.into_iter()
+        .filter(|file| file.ends_with(".v") || file.ends_with(".sv"))
+        .map(|file| tmp_path.join(file))
+        .collect();
+    println!("Indexing {} source files to find modules by name...", sources.len());
+    materialize_files(tmp_path, &sources)?;
+    let mut index = ModuleFiles::new();
+    for (path, module) in DesignIndex::from_files_with_defines(&sources, defines).modules() {
+        let files = index.entry(module.name.clone()).or_default();
+        if !files.iter().any(|f| f == path) {
+            files.push(path.to_path_buf());
+        }
+    }
+    INDEXES.lock().unwrap().insert(tmp_path.to_path_buf(), index.clone());
+    Ok(index)
+}
+
+fn find_matching_entries(module_name: &str, tmp_path: &PathBuf) -> Result<Vec<PathBuf>> {
+    let candidates = [format!("{}.sv", module_name), format!("{}.v", module_name)];
+    Ok(list_repo_files(tmp_path)?
         .into_iter()
-        .filter_map(Result::ok)
-        .filter(|entry| {
-            entry.file_name().to_str() == Some(&format!("{}.sv", module_name)) || 
-            entry.file_name().to_str() == Some(&format!("{}.v", module_name))
+        .filter(|file| {
+            let file_name = Path::new(file).file_name().and_then(|f| f.to_str()).unwrap_or_default();
+            candidates.iter().any(|c| c == file_name)
         })
-        .map(|entry| entry.path().to_path_buf())
-        .collect()
+        .map(|file| tmp_path.join(file))
+        .collect())
 }
 
-fn process_multiple_matches(matching_entries: Vec<PathBuf>, target_path: &PathBuf, module_name: &str, url: &str, visited: &mut HashSet<String>, is_top_module: bool, processed_modules: &mut HashSet<String>) -> Result<()> {
-    println!("Multiple modules found for '{}'. Please choose:", module_name);
-    for (i, entry) in matching_entries.iter().enumerate() {
-        println!("{}: {}", i + 1, entry.display());
+/// Picks one of several files declaring `module_name`. A choice recorded in vpm.lock wins;
+/// otherwise candidates are ranked by the search paths in vpm.toml, closeness to the parent,
+/// not being test or example code, and the parent's file extension. Only a tie at the top
+/// is asked about.
+fn choose_match(matching_entries: Vec<PathBuf>, module_name: &str, tmp_path: &Path, destination: &Path, parent: Option<&Path>) -> Result<PathBuf> {
+    let origin_of = |path: &Path| path.strip_prefix(tmp_path).unwrap_or(path).to_string_lossy().replace('\\', "/");
+    let lock_entries = read_lockfile().unwrap_or_default();
+
+    let remembered = lock_entries.iter()
+        .filter(|entry| Path::new(&entry.full_path).parent() == Some(destination) && entry.modules.iter().any(|m| m == module_name))
+        .find_map(|entry| entry.origin.as_ref().and_then(|origin| matching_entries.iter().find(|path| &origin_of(path) == origin)));
+    if let Some(path) = remembered {
+        println!("Using '{}' for module '{}' as recorded in vpm.lock", origin_of(path), module_name);
+        return Ok(path.clone());
     }
 
-    let mut choice = String::new();
-    std::io::stdin().read_line(&mut choice)?;
-    let index: usize = choice.trim().parse()?;
+    let search_paths = get_search_paths();
+    let parent_origin = parent.and_then(|parent| lock_entries.iter().find(|entry| Path::new(&entry.full_path) == parent)).and_then(|entry| entry.origin.clone());
+    let parent_dir = parent_origin.as_deref().and_then(|origin| Path::new(origin).parent()).map(Path::to_path_buf);
+    let parent_extension = parent.and_then(|parent| parent.extension()).and_then(|ext| ext.to_str());
+    let rank = |path: &PathBuf| {
+        let origin = origin_of(path);
+        let origin = Path::new(&origin);
+        let searched = search_paths.iter().any(|dir| origin.starts_with(dir.trim_start_matches("./")));
+        let near_parent = parent_dir.as_deref().is_some_and(|dir| origin.parent() == Some(dir));
+        let non_design = origin.parent().into_iter().flat_map(|dir| dir.components())
+            .any(|c| NON_DESIGN_DIRS.contains(&c.as_os_str().to_string_lossy().to_lowercase().as_str()));
+        let same_extension = parent_extension.is_none_or(|ext| origin.extension().and_then(|e| e.to_str()) == Some(ext));
+        (!searched, !near_parent, non_design, !same_extension)
+    };
 
-    if index > 0 && index <= matching_entries.len() {
-        let dir_entry = filepath_to_dir_entry(matching_entries[index - 1].clone())?;
-        process_file(&dir_entry, target_path.to_str().unwrap(), module_name, url, visited, is_top_module)?;
-        processed_modules.insert(format!("{}.v", module_name));
-    } else {
-        anyhow::bail!("Invalid choice");
+    let mut ranked = matching_entries;
+    ranked.sort_by_key(|path| (rank(path), origin_of(path)));
+    let best = rank(&ranked[0]);
+    let tied: Vec<PathBuf> = ranked.iter().take_while(|path| rank(path) == best).cloned().collect();
+    if tied.len() == 1 {
+        println!("Using '{}' for module '{}' (other candidates: {})", origin_of(&tied[0]), module_name,
+            ranked[1..].iter().map(|path| origin_of(path)).collect::<Vec<_>>().join(", "));
+        return Ok(tied[0].clone());
+    }
+    if !io::stdin().is_terminal() {
+        println!("Warning: {} files declare module '{}' equally well. Using '{}'; add its directory to [resolve] search_paths in vpm.toml to choose another.", tied.len(), module_name, origin_of(&tied[0]));
+        return Ok(tied[0].clone());
     }
+    // Modules synced in parallel ask one question at a time.
+    static PROMPT: Mutex<()> = Mutex::new(());
+    let _guard = PROMPT.lock().unwrap_or_else(|e| e.into_inner());
+    let items: Vec<String> = tied.iter().map(|path| origin_of(path)).collect();
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("Several files declare module '{}'. Choose one", module_name))
+        .items(&items)
+        .default(0)
+        .interact()?;
+    Ok(tied[selection].clone())
+}
 
-    Ok(())
+/// Adds `module_name` to the modules recorded on the lockfile entry of `vendored_path`.
+fn record_module(vendored_path: &Path, module_name: &str) -> Result<()> {
+    // Read and written under one lock, so modules recorded concurrently are not lost.
+    edit_lockfile(|lockfile| {
+        let mut modules = parse_lockfile(lockfile)?.into_iter()
+            .find(|entry| Path::new(&entry.full_path) == vendored_path)
+            .map(|entry| entry.modules)
+            .unwrap_or_default();
+        if !modules.iter().any(|m| m == module_name) {
+            modules.push(module_name.to_string());
+            set_lock_field(lockfile, vendored_path, "modules", &format!("[{}]", modules.iter().map(|m| format!("\"{}\"", m)).collect::<Vec<_>>().join(", ")));
+        }
+        Ok(())
+    })
 }
 
-fn process_file(entry: &DirEntry, destination: &str, module_path: &str, url: &str, visited: &mut HashSet<String>, is_top_module: bool) -> Result<()> {
+/// The vendored file in `destination` that declares `module_name`, according to the
+/// lockfile.
+fn vendored_module_file(destination: &Path, module_name: &str) -> Option<PathBuf> {
+    read_lockfile().ok()?.into_iter()
+        .find(|entry| entry.modules.iter().any(|m| m == module_name) && Path::new(&entry.full_path).parent() == Some(destination))
+        .map(|entry| PathBuf::from(entry.full_path))
+}
+
+fn process_file(entry: &DirEntry, destination: &str, module_path: &str, url: &str, run: &mut IncludeRun, is_top_module: bool) -> Result<()> {
     let target_path = PathBuf::from(destination);
     let extension = entry.path().extension().and_then(|s| s.to_str()).unwrap_or("v");
     fs::copy(entry.path(), &target_path.join(entry.file_name()))?;
 
     let contents = fs::read_to_string(entry.path())?;
-    let mut parser = Parser::new();
-    parser.set_language(tree_sitter_verilog::language())?;
-    let tree = parser.parse(&contents, None).context("Failed to parse file")?;
-
-    let header_content = generate_headers(tree.root_node(), &contents)?;
+    match write_stubs(entry.path(), &target_path, run.options.stubs, run.options.defines.as_ref()) {
+        Ok(written) => written.iter().for_each(|file| println!("Generated {}", file)),
+        Err(e) => eprintln!("Warning: Failed to generate stubs for {}: {}", entry.path().display(), e),
+    }
     let module_name = Path::new(module_path)
         .file_stem()
         .and_then(|s| s.to_str())
@@ -469,19 +741,14 @@ fn process_file(entry: &DirEntry, destination: &str, module_path: &str, url: &st
     } else {
         module_name.to_string()
     };
-    let header_filename = format!("{}.{}", module_name.strip_suffix(".v").unwrap_or(module_name), if extension == "sv" { "svh" } else { "vh" });
-    let headers_dir = target_path.join("headers");
-    fs::create_dir_all(&headers_dir)?;
-    fs::write(headers_dir.join(&header_filename), header_content)?;
-    println!("Generating header file: {}", target_path.join(&header_filename).to_str().unwrap());
 
     let full_module_path = target_path.join(&module_name_with_ext);
-    update_lockfile(&full_module_path, url, &contents, visited, is_top_module)?;
+    update_lockfile(&full_module_path, url, &contents, &run.visited, is_top_module, run.options.defines.as_ref())?;
 
     Ok(())
 }
 
-fn download_and_process_submodules(package_name: &str, module_path: &str, destination: &str, url: &str, visited: &mut HashSet<String>, _is_top_module: bool, commit_hash: Option<&str>) -> Result<HashSet<String>> {
+fn download_and_process_submodules(package_name: &str, module_path: &str, destination: &str, url: &str, run: &mut IncludeRun, _is_top_module: bool, commit_hash: Option<&str>) -> Result<HashSet<String>> {
     let module_name = Path::new(module_path)
         .file_stem()
         .and_then(|s| s.to_str())
@@ -505,21 +772,38 @@ fn download_and_process_submodules(package_name: &str, module_path: &str, destin
         }
     };
     
-    let mut parser = Parser::new();
-    if let Err(e) = parser.set_language(tree_sitter_verilog::language()) {
-        eprintln!("Warning: Failed to set parser language: {}. Skipping submodule processing.", e);
-        return Ok(HashSet::new());
-    }
+    // Includes are vendored first, so that macros they define are known when the
+    // instantiations are found.
+    let mut all_submodules = HashSet::new();
+    let interfaces = match process_dependencies(package_name, &full_module_path, &contents, destination, url, run, commit_hash) {
+        Ok((processed, interfaces)) => {
+            all_submodules.extend(processed);
+            interfaces
+        }
+        Err(e) => {
+            eprintln!("Warning: Failed to resolve includes and packages of {}: {}. Continuing without them.", full_module_path.display(), e);
+            Vec::new()
+        }
+    };
 
-    let submodules = match get_submodules(&contents) {
-        Ok(s) => s,
+    let submodules = match get_submodules(&contents, &[PathBuf::from(destination)], run.options.defines.as_ref()) {
+        Ok(submodules) => submodules,
         Err(e) => {
             eprintln!("Warning: Failed to get submodules from {}: {}. Continuing without submodules.", full_module_path.display(), e);
-            HashSet::new()
+            Vec::new()
         }
     };
-
-    let mut all_submodules = HashSet::new();
+    let (mut submodules, primitives) = split_primitives(submodules);
+    if !primitives.is_empty() {
+        println!("Leaving vendor primitives as black boxes: {}", primitives.iter()
+            .map(|p| format!("{} ({})", p, primitive_library(p).unwrap_or_default()))
+            .collect::<Vec<_>>().join(", "));
+    }
+    // Modules declared in the same file come with it.
+    let declared: Vec<String> = parse_file_with_defines(&full_module_path, run.options.defines.as_ref()).map(|modules| modules.into_iter().map(|m| m.name).collect()).unwrap_or_default();
+    submodules.retain(|submodule| !declared.contains(submodule));
+    // Interfaces are found and vendored by name like modules.
+    submodules.extend(interfaces.into_iter().filter(|i| !submodules.contains(i)).collect::<Vec<_>>());
 
     for submodule in submodules {
         let submodule_with_ext = if submodule.ends_with(".v") || submodule.ends_with(".sv") {
@@ -531,7 +815,7 @@ fn download_and_process_submodules(package_name: &str, module_path: &str, destin
                 .unwrap_or("v");
             format!("{}.{}", &submodule, parent_extension)
         };
-        if !visited.contains(&submodule_with_ext) {
+        if !run.visited.contains(&submodule_with_ext) {
             let submodule_destination = PathBuf::from(destination);
             if let Err(e) = fs::create_dir_all(&submodule_destination) {
                 eprintln!("Warning: Failed to create directory {}: {}. Skipping this submodule.", submodule_destination.display(), e);
@@ -542,9 +826,9 @@ fn download_and_process_submodules(package_name: &str, module_path: &str, destin
                 package_name,
                 &submodule_with_ext,
                 submodule_destination.to_str().unwrap().to_string(),
-                visited,
+                run,
                 &url,
-                false,
+                Some(&full_module_path),
                 commit_hash.clone()
             ) {
                 Ok(processed_submodules) => {
@@ -557,8 +841,14 @@ fn download_and_process_submodules(package_name: &str, module_path: &str, destin
                 }
             }
 
-            let full_submodule_path = submodule_destination.join(&submodule_with_ext);
-            if let Err(e) = update_lockfile(&full_submodule_path, &url, &contents, visited, false) {
+            let full_submodule_path = vendored_module_file(&submodule_destination, &submodule)
+                .unwrap_or_else(|| submodule_destination.join(&submodule_with_ext));
+            // The checksum must cover the file that was written; without it there is nothing to record.
+            let Ok(submodule_contents) = fs::read_to_string(&full_submodule_path) else {
+                eprintln!("Warning: Could not read {}. Not recording it in vpm.lock.", full_submodule_path.display());
+                continue;
+            };
+            if let Err(e) = update_lockfile(&full_submodule_path, &url, &submodule_contents, &run.visited, false, run.options.defines.as_ref()) {
                 eprintln!("Warning: Failed to update lockfile for {}: {}. Continuing without updating lockfile.", full_submodule_path.display(), e);
             }
         }
@@ -567,183 +857,213 @@ fn download_and_process_submodules(package_name: &str, module_path: &str, destin
     Ok(all_submodules)
 }
 
-fn update_lockfile(full_path: &PathBuf, url: &str, contents: &str, visited: &HashSet<String>, is_top_module: bool) -> Result<()> {
-    let mut lockfile = fs::read_to_string("vpm.lock").unwrap_or_default();
-    let module_entry = if is_top_module {
-        format!("[[package]]\nfull_path = \"{}\"\nsource = \"{}\"\nparents = []\n", full_path.display(), url)
-    } else {
-        format!("[[package]]\nfull_path = \"{}\"\nsource = \"{}\"\n", full_path.display(), url)
-    };
-
-    let mut parser = Parser::new();
-    parser.set_language(tree_sitter_verilog::language())?;
-    let submodules = get_submodules(contents)?;
-    let submodules_vec: Vec<String> = submodules.into_iter().collect();
-
-    if !lockfile.contains(&format!("full_path = \"{}\"", full_path.display())) {
-        let formatted_submodules = submodules_vec.iter()
-            .map(|s| format!("  \"{}\",", s))
-            .collect::<Vec<_>>()
-            .join("\n");
-        lockfile.push_str(&format!("\n{}\nsubmodules = [\n{}\n]\n", module_entry, formatted_submodules));
-    } else {
-        update_submodules(&mut lockfile, &module_entry, &submodules_vec);
-    }
-
-    for submodule in &submodules_vec {
-        if !visited.contains(submodule) {
-            let submodule_path = full_path.parent().unwrap().join(submodule);
-            if let Some(existing_entry) = lockfile.find(&format!("\n[[package]]\nfull_path = \"{}\"", submodule_path.display())) {
-                let parent_start = lockfile[existing_entry..].find("parents = [").map(|i| existing_entry + i);
-                if let Some(start) = parent_start {
-                    let end = lockfile[start..].find(']').map(|i| start + i + 1).unwrap_or(lockfile.len());
-                    let current_parents = lockfile[start..end].to_string();
-                    let new_parents = if current_parents.contains(&full_path.display().to_string()) {
-                        current_parents
-                    } else {
-                        format!("{}  \"{}\",\n]", &current_parents[..current_parents.len() - 1], full_path.display())
-                    };
-                    lockfile.replace_range(start..end, &new_parents);
-                }
-            } else {
-                let submodule_entry = format!("\n[[package]]\nfull_path = \"{}\"\nsource = \"{}\"\nparents = [\n  \"{}\",\n]\nsubmodules = []\n", submodule_path.display(), url, full_path.display());
-                lockfile.push_str(&submodule_entry);
+/// Vendors the files `contents` pulls in with `` `include `` and the files declaring the
+/// packages it uses, and records both on the lockfile entry of `vendored_path`. Returns the
+/// vendored files and the interfaces `contents` uses as port types.
+fn process_dependencies(package_name: &str, vendored_path: &Path, contents: &str, destination: &str, url: &str, run: &mut IncludeRun, commit_hash: Option<&str>) -> Result<(HashSet<String>, Vec<String>)> {
+    let tmp_path = PathBuf::from("/tmp").join(package_name);
+    let repo_files = list_repo_files(&tmp_path)?;
+    let include_dirs = [PathBuf::from(destination)];
+    // Relative includes resolve against the directory the file came from in the repository.
+    let repo_dir = read_lockfile()?.into_iter()
+        .find(|entry| Path::new(&entry.full_path) == vendored_path)
+        .and_then(|entry| entry.origin)
+        .and_then(|origin| Path::new(&origin).parent().map(Path::to_path_buf))
+        .unwrap_or_default();
+
+    let mut processed = HashSet::new();
+    let mut includes = Vec::new();
+    let mut handled: Vec<String> = Vec::new();
+    // With a define set, vendoring a header can activate further includes, so the
+    // includes are looked up again until no new ones appear.
+    loop {
+        let pending: Vec<String> = source_dependencies(contents, &include_dirs, run.options.defines.as_ref())?.includes.into_iter()
+            .filter(|include| !handled.contains(include))
+            .collect();
+        if pending.is_empty() {
+            break;
+        }
+        handled.extend(pending.iter().cloned());
+        for include in &pending {
+            if !Path::new(include).components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+                println!("Warning: Included file '{}' is outside the vendored directory. Skipping...", include);
+                continue;
             }
+            let Some(found) = find_include(&repo_files, &repo_dir, include) else {
+                println!("Warning: Included file '{}' not found in the repository. Skipping...", include);
+                continue;
+            };
+            includes.push(include.clone());
+            if !run.visited.insert(include.clone()) {
+                continue;
+            }
+            println!("Including file '{}'", include);
+            let source = tmp_path.join(&found);
+            materialize_files(&tmp_path, std::slice::from_ref(&source))?;
+            // Keep the path as written so the directive resolves against the destination.
+            let target = Path::new(destination).join(include);
+            fs::create_dir_all(target.parent().unwrap())?;
+            fs::copy(&source, &target)?;
+            let header_contents = fs::read_to_string(&target)?;
+            update_lockfile(&target, url, &header_contents, &run.visited, false, run.options.defines.as_ref())?;
+            edit_lockfile(|lockfile| {
+                set_lock_field(lockfile, &target, "origin", &format!("\"{}\"", found));
+                Ok(())
+            })?;
+            processed.insert(include.clone());
+            let (nested, _) = process_dependencies(package_name, &target, &header_contents, destination, url, run, commit_hash)?;
+            processed.extend(nested);
         }
     }
 
-    fs::write("vpm.lock", lockfile)?;
-    Ok(())
+    let dependencies = source_dependencies(contents, &include_dirs, run.options.defines.as_ref())?;
+    let mut packages = Vec::new();
+    for package in &dependencies.packages {
+        let Some(found) = find_package(&tmp_path, &repo_files, package, run.options.defines.as_ref())? else {
+            println!("Warning: Package '{}' is not declared in the repository. Skipping...", package);
+            continue;
+        };
+        packages.push(package.clone());
+        processed.extend(process_module(package_name, &found, destination.to_string(), run, url, Some(vendored_path), commit_hash)?);
+    }
+
+    if !includes.is_empty() || !packages.is_empty() {
+        let quoted = |items: &[String]| format!("[{}]", items.iter().map(|i| format!("\"{}\"", i)).collect::<Vec<_>>().join(", "));
+        edit_lockfile(|lockfile| {
+            if !includes.is_empty() {
+                set_lock_field(lockfile, vendored_path, "includes", &quoted(&includes));
+            }
+            if !packages.is_empty() {
+                set_lock_field(lockfile, vendored_path, "packages", &quoted(&packages));
+            }
+            Ok(())
+        })?;
+    }
+    Ok((processed, dependencies.interfaces))
 }
 
-fn update_submodules(lockfile: &mut String, module_entry: &str, submodules: &[String]) {
-    if let Some(start) = lockfile.find(module_entry).and_then(|pos| lockfile[pos..].find("submodules = [").map(|offset| pos + offset)) {
-        let end = lockfile[start..].find(']').map(|pos| start + pos + 1).unwrap_or(lockfile.len());
-        let new_modules = format!("submodules = [\n{}\n]", submodules.iter().map(|m| format!("  \"{}\",", m)).collect::<Vec<_>>().join("\n"));
-        lockfile.replace_range(start..end, &new_modules);
+/// Finds the repository file an `` `include `` refers to: next to the including file first,
+/// then anywhere in the repository, preferring the shortest path.
+fn find_include(repo_files: &[String], repo_dir: &Path, include: &str) -> Option<String> {
+    let next_to = repo_dir.join(include).to_string_lossy().replace('\\', "/");
+    if repo_files.contains(&next_to) {
+        return Some(next_to);
     }
+    repo_files.iter()
+        .filter(|file| *file == include || file.ends_with(&format!("/{}", include)))
+        .min_by_key(|file| file.len())
+        .cloned()
 }
 
-pub fn generate_headers(root_node: Node, contents: &str) -> Result<String> {
-    static QUERY: Lazy<Query> = Lazy::new(|| {
-        Query::new(
-            tree_sitter_verilog::language(),
-            "(module_declaration
-                (module_header
-                    (module_keyword)
-                    (simple_identifier) @module_name)
-                (module_nonansi_header
-                    (parameter_port_list)? @params
-                    (list_of_ports) @ports)
-            )
-            (module_declaration
-                (module_header
-                    (module_keyword)
-                    (simple_identifier) @module_name)
-                (module_ansi_header
-                    (parameter_port_list)? @params
-                    (list_of_port_declarations)? @ports)
-            )",
-        )
-        .expect("Failed to create query")
-    });
-
-    let mut query_cursor = QueryCursor::new();
-    let matches = query_cursor.matches(&QUERY, root_node, contents.as_bytes());
-
-    let mut header_content = String::new();
-
-    for match_ in matches {
-        let mut module_name = "";
-        let mut params = "";
-        let mut ports = "";
-
-        for capture in match_.captures {
-            let capture_text = &contents[capture.node.byte_range()];
-            match capture.index {
-                0 => module_name = capture_text,
-                1 => params = capture_text,
-                2 => ports = capture_text,
-                _ => {}
+/// Finds the repository file that declares `package`. Files named after the package are
+/// checked first so that usually only one file has to be fetched.
+fn find_package(tmp_path: &Path, repo_files: &[String], package: &str, defines: Option<&DefineSet>) -> Result<Option<String>> {
+    let (likely, rest): (Vec<&String>, Vec<&String>) = repo_files.iter()
+        .filter(|file| is_source(Path::new(file)))
+        .partition(|file| Path::new(file).file_stem().and_then(|s| s.to_str()).is_some_and(|stem| stem.contains(package)));
+    for batch in [likely, rest] {
+        let paths: Vec<PathBuf> = batch.iter().map(|file| tmp_path.join(file)).collect();
+        materialize_files(tmp_path, &paths)?;
+        for (file, path) in batch.iter().zip(&paths) {
+            let Ok(contents) = fs::read_to_string(path) else { continue };
+            if source_dependencies(&contents, &[], defines).is_ok_and(|d| d.declared_packages.iter().any(|p| p == package)) {
+                return Ok(Some(file.to_string()));
             }
         }
-        
-        header_content.push_str(&format!(
-            "`ifndef {}_H\n`define {}_H\n\n",
-            module_name.to_uppercase(),
-            module_name.to_uppercase()
-        ));
-
-        if !params.is_empty() {
-            header_content.push_str(&format!(
-                "// Parameters\n{}\n\n",
-                params.trim()
-            ));
+    }
+    Ok(None)
+}
+
+pub fn update_lockfile(full_path: &PathBuf, url: &str, contents: &str, visited: &HashSet<String>, is_top_module: bool, defines: Option<&DefineSet>) -> Result<()> {
+    let url = &strip_credentials(url);
+    let module_entry = if is_top_module {
+        format!("[[package]]\nfull_path = \"{}\"\nsource = \"{}\"\nparents = []\n", full_path.display(), url)
+    } else {
+        format!("[[package]]\nfull_path = \"{}\"\nsource = \"{}\"\n", full_path.display(), url)
+    };
+
+    let (submodules_vec, blackboxes) = split_primitives(get_submodules(contents, &[full_path.parent().map(Path::to_path_buf).unwrap_or_default()], defines)?);
+
+    edit_lockfile(|lockfile| {
+        if !lockfile.contains(&format!("full_path = \"{}\"", full_path.display())) {
+            let formatted_submodules = submodules_vec.iter()
+                .map(|s| format!("  \"{}\",", s))
+                .collect::<Vec<_>>()
+                .join("\n");
+            lockfile.push_str(&format!("\n{}\nsubmodules = [\n{}\n]\n", module_entry, formatted_submodules));
+        } else {
+            update_submodules(lockfile, &module_entry, &submodules_vec);
+        }
+        // The checksum lets later commands detect local modifications to the vendored file.
+        set_lock_field(lockfile, full_path, "checksum", &format!("\"{}\"", sha256_checksum(contents.as_bytes())));
+        if !blackboxes.is_empty() {
+            set_lock_field(lockfile, full_path, "blackboxes", &format!("[{}]", blackboxes.iter().map(|b| format!("\"{}\"", b)).collect::<Vec<_>>().join(", ")));
         }
 
-        if !ports.is_empty() {
-            header_content.push_str(&format!(
-                "// Ports\n{}\n\n",
-                ports.trim()
-            ));
+        for submodule in &submodules_vec {
+            if !visited.contains(submodule) {
+                let submodule_path = full_path.parent().unwrap().join(submodule);
+                if let Some(existing_entry) = lockfile.find(&format!("\n[[package]]\nfull_path = \"{}\"", submodule_path.display())) {
+                    let parent_start = lockfile[existing_entry..].find("parents = [").map(|i| existing_entry + i);
+                    if let Some(start) = parent_start {
+                        let end = lockfile[start..].find(']').map(|i| start + i + 1).unwrap_or(lockfile.len());
+                        let current_parents = lockfile[start..end].to_string();
+                        let new_parents = if current_parents.contains(&full_path.display().to_string()) {
+                            current_parents
+                        } else {
+                            format!("{}  \"{}\",\n]", &current_parents[..current_parents.len() - 1], full_path.display())
+                        };
+                        lockfile.replace_range(start..end, &new_parents);
+                    }
+                } else {
+                    let submodule_entry = format!("\n[[package]]\nfull_path = \"{}\"\nsource = \"{}\"\nparents = [\n  \"{}\",\n]\nsubmodules = []\n", submodule_path.display(), url, full_path.display());
+                    lockfile.push_str(&submodule_entry);
+                }
+            }
         }
+        Ok(())
+    })
+}
 
-        header_content.push_str(&format!(
-            "// Module: {}\n// TODO: Add module description\n\n`endif // {}_H\n\n",
-            module_name,
-            module_name.to_uppercase()
-        ));
+/// Sets `key = value` (a TOML literal) on the lockfile entry of `full_path`, replacing any
+/// previous value.
+fn set_lock_field(lockfile: &mut String, full_path: &Path, key: &str, value: &str) {
+    let marker = format!("[[package]]\nfull_path = \"{}\"\n", full_path.display());
+    let Some(start) = lockfile.find(&marker) else { return };
+    let end = lockfile[start + 1..].find("\n[[").map(|i| start + 1 + i).unwrap_or(lockfile.len());
+    let line = format!("{} = {}\n", key, value);
+    match lockfile[start..end].find(&format!("\n{} = ", key)) {
+        Some(offset) => {
+            let line_start = start + offset + 1;
+            let line_end = lockfile[line_start..].find('\n').map(|i| line_start + i + 1).unwrap_or(lockfile.len());
+            lockfile.replace_range(line_start..line_end, &line);
+        }
+        None => {
+            let source_line = lockfile[start..end].find("\nsource = ").map(|i| start + i + 1);
+            let insert_at = source_line
+                .and_then(|line_start| lockfile[line_start..].find('\n').map(|i| line_start + i + 1))
+                .unwrap_or(start + marker.len());
+            lockfile.insert_str(insert_at, &line);
+        }
     }
-
-    Ok(header_content)
 }
 
-
-pub fn get_submodules(contents: &str) -> Result<HashSet<String>> {
-    static REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(
-        r"(?mi)^\s*(?!(always(_comb|_ff|_latch)?|assert|assign|assume|begin|case|cover|else|end(case|function|generate|module|primitive|table|task)?|enum|for|forever|function|generate|if|initial|input|int|localparam|logic|module|negedge|output|param(eter)?|posedge|primitive|real|reg|repeat|table|task|time|timescale|typedef|while|wire))(\w+)\s*(?:#\([\s.\w(\[\-:\]\),{'}`/+!~@#$%^&*=<>?]+\))?\s*[\w\[:\]]+\s*(?:\([\s.\w(\[\-:\]\),{'}`/+!~@#$%^&*=<>?|]+\));"
-    ).unwrap());
-    let submodules: HashSet<String> = REGEX
-        .captures_iter(contents) // Iterate over captures
-        .map(|caps| caps.unwrap().get(0).unwrap().as_str()) // Extract the matched string
-        .map(|s| s.split_whitespace().next().unwrap().to_string()) // Split and get submodule name
-        .collect(); // Collect into a HashSet
-    // for submodule in &submodules {
-        // println!("Found submodule: {}", submodule);
-    // }
-    Ok(submodules)
+fn update_submodules(lockfile: &mut String, module_entry: &str, submodules: &[String]) {
+    if let Some(start) = lockfile.find(module_entry).and_then(|pos| lockfile[pos..].find("submodules = [").map(|offset| pos + offset)) {
+        let end = lockfile[start..].find(']').map(|pos| start + pos + 1).unwrap_or(lockfile.len());
+        let new_modules = format!("submodules = [\n{}\n]", submodules.iter().map(|m| format!("  \"{}\",", m)).collect::<Vec<_>>().join("\n"));
+        lockfile.replace_range(start..end, &new_modules);
+    }
 }
 
-pub fn include_repo_from_url(url: &str, location: &str, commit_hash: Option<&str>) -> Result<()> {
+pub fn include_repo_from_url(url: &str, location: &str, commit_hash: Option<&str>, sparse: bool, archive: Option<&VendorArchive>) -> Result<String> {
     let repo_path = Path::new(location).join(name_from_url(url));
     let pb = ProgressBar::new_spinner();
     pb.set_style(ProgressStyle::default_spinner().template("{spinner} {msg}").unwrap());
     pb.set_message("Reading repository...");
     pb.enable_steady_tick(std::time::Duration::from_millis(100));
-    clone_repo(url, &repo_path, commit_hash)?;
-    pb.finish_with_message("Reading repository complete");
-    Ok(())
+    let start = Instant::now();
+    let commit = fetch_repository(archive, url, &repo_path, commit_hash, sparse, Some(&pb))?;
+    pb.finish_with_message(format!("Reading repository complete ({:.2?})", start.elapsed()));
+    Ok(commit)
 }
-
-pub fn clone_repo(url: &str, repo_path: &Path, commit_hash: Option<&str>) -> Result<()> {
-    if repo_path.exists() {
-        fs::remove_dir_all(repo_path)?;
-    }
-    Command::new("git")
-        .args([ "clone", "--depth", "1", "--single-branch", "--jobs", "4",
-            url, repo_path.to_str().unwrap_or_default(),
-        ])
-        .stdout(std::process::Stdio::null())
-        .stderr(std::process::Stdio::null())
-        .status()
-        .with_context(|| format!("Failed to clone repository from URL: '{}'", url))?;
-    if let Some(hash) = commit_hash {
-        Command::new("git")
-            .args([ "-C", repo_path.to_str().unwrap_or_default(), "checkout", hash ])
-            .stdout(std::process::Stdio::null())
-            .stderr(std::process::Stdio::null())
-            .status()
-            .with_context(|| format!("Failed to checkout commit hash: '{}'", hash))?;
-    }
-    Ok(())
-}
\ No newline at end of file
@@ -0,0 +1,111 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cmd::{Execute, Info};
+use crate::design::{parse_file, DesignIndex, ModuleInterface};
+use crate::git::strip_credentials;
+use crate::lockfile::read_lockfile;
+use crate::toml::get_top_modules;
+
+impl Execute for Info {
+    async fn execute(&self) -> Result<()> {
+        let design = DesignIndex::load();
+        let (file, interface) = find_module(&self.module, &design)?;
+
+        println!("Module: {}", interface.name);
+        println!("File: {}", file.display());
+        match module_origin(&file)? {
+            Some((source, commits)) => {
+                println!("Package: {}", source);
+                if !commits.is_empty() {
+                    println!("Locked commit: {}", commits.join(", "));
+                }
+            }
+            None => println!("Package: local (not vendored)"),
+        }
+
+        println!("Parameters:");
+        if interface.parameters.is_empty() {
+            println!("  (none)");
+        }
+        for (name, default) in &interface.parameters {
+            match default {
+                Some(default) => println!("  {} = {}", name, default),
+                None => println!("  {}", name),
+            }
+        }
+
+        println!("Ports:");
+        if interface.ports.is_empty() {
+            println!("  (none)");
+        }
+        for (direction, width, name) in &interface.ports {
+            println!("  {:<7}{:<12}{}", direction, width.as_deref().unwrap_or(""), name);
+        }
+
+        println!("Instantiates:");
+        if interface.submodules.is_empty() {
+            println!("  (none)");
+        }
+        for submodule in &interface.submodules {
+            match design.find_module(submodule) {
+                Some((path, _)) => println!("  {} ({})", submodule, path.display()),
+                None => println!("  {} (not found in project)", submodule),
+            }
+        }
+
+        println!("Instantiated by:");
+        let mut dependents = Vec::new();
+        for (path, module) in design.modules() {
+            if module.submodules.contains(&interface.name) {
+                dependents.push(format!("{} ({})", module.name, path.display()));
+            }
+        }
+        if dependents.is_empty() {
+            println!("  (none)");
+        }
+        for dependent in dependents {
+            println!("  {}", dependent);
+        }
+        Ok(())
+    }
+}
+
+/// Finds the declaration of `module` (a module name or a path to its file) in `design`.
+pub fn find_module(module: &str, design: &DesignIndex) -> Result<(PathBuf, ModuleInterface)> {
+    let path = Path::new(module);
+    if path.is_file() {
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+        let modules = parse_file(path)?;
+        let interface = modules.iter().find(|m| m.name == stem).or(modules.first()).cloned()
+            .with_context(|| format!("No module declaration found in '{}'", module))?;
+        return Ok((path.to_path_buf(), interface));
+    }
+    let name = module.trim_end_matches(".sv").trim_end_matches(".v");
+    design.find_module(name)
+        .map(|(path, interface)| (path.to_path_buf(), interface.clone()))
+        .with_context(|| format!("Module '{}' not found in the project. Include it first with 'vpm include'.", name))
+}
+
+/// Returns the source recorded in vpm.lock for the vendored file at `path`, along with
+/// the commits vpm.toml locks that source to. Returns `None` for files vpm did not vendor.
+fn module_origin(path: &Path) -> Result<Option<(String, Vec<String>)>> {
+    let canonical = fs::canonicalize(path)?;
+    let entry = read_lockfile()?.into_iter().find(|entry| {
+        fs::canonicalize(&entry.full_path).is_ok_and(|locked| canonical.starts_with(locked))
+    });
+    let Some(entry) = entry else {
+        return Ok(None);
+    };
+    let commits = get_top_modules().into_iter()
+        .filter(|(repo_link, _, _)| strip_credentials(repo_link) == entry.source)
+        .map(|(_, _, commit)| commit)
+        .fold(Vec::new(), |mut commits, commit| {
+            if !commits.contains(&commit) {
+                commits.push(commit);
+            }
+            commits
+        });
+    Ok(Some((entry.source, commits)))
+}
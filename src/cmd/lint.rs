@@ -0,0 +1,426 @@
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use toml_edit::DocumentMut;
+
+use crate::cmd::info::find_module;
+use crate::cmd::{Execute, Lint};
+use crate::design::{is_source, project_sources, DesignIndex};
+use crate::error::SilentExit;
+use crate::preprocess::parse_define;
+use crate::targets::select_sources;
+use crate::toml::get_lint_waivers;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// One finding of a linter, in the same shape whichever tool reported it.
+#[derive(Debug, Clone)]
+struct Diagnostic {
+    file: String,
+    line: usize,
+    column: usize,
+    severity: Severity,
+    tool: &'static str,
+    rule: String,
+    message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.file.is_empty() {
+            return write!(f, "{}: {}: {} [{}/{}]", self.tool, self.severity, self.message, self.tool, self.rule);
+        }
+        write!(f, "{}:{}:{}: {}: {} [{}/{}]", self.file, self.line, self.column, self.severity, self.message, self.tool, self.rule)
+    }
+}
+
+/// A `[[waiver]]` entry of a waiver file. Every field that is set must match; `file` and
+/// `rule` may contain `*` and `?` wildcards.
+#[derive(Debug, Clone, Default)]
+struct Waiver {
+    source: String,
+    tool: Option<String>,
+    rule: Option<String>,
+    file: Option<String>,
+    line: Option<usize>,
+    message: Option<String>,
+}
+
+impl Waiver {
+    fn matches(&self, diagnostic: &Diagnostic) -> bool {
+        self.tool.as_ref().is_none_or(|tool| tool == diagnostic.tool)
+            && self.rule.as_ref().is_none_or(|rule| wildcard_match(rule, &diagnostic.rule))
+            && self.file.as_ref().is_none_or(|file| wildcard_match(file.trim_start_matches("./"), &diagnostic.file))
+            && self.line.is_none_or(|line| line == diagnostic.line)
+            && self.message.as_ref().is_none_or(|message| diagnostic.message.contains(message.as_str()))
+    }
+}
+
+/// The files, include directories and defines to lint.
+struct LintSources {
+    files: Vec<PathBuf>,
+    include_dirs: Vec<PathBuf>,
+    defines: Vec<(String, Option<String>)>,
+    top: Option<String>,
+}
+
+impl Execute for Lint {
+    async fn execute(&self) -> Result<()> {
+        let sources = self.lint_sources()?;
+        anyhow::ensure!(!sources.files.is_empty(), "No Verilog or SystemVerilog files to lint");
+        println!("Linting {} files", sources.files.len());
+
+        let tools: Vec<&str> = if self.tool.is_empty() { vec!["verible", "verilator"] } else { self.tool.iter().map(|t| t.as_str()).collect() };
+        let mut diagnostics = Vec::new();
+        let mut ran = 0;
+        for tool in tools {
+            let (binary, hint) = match tool {
+                "verible" => ("verible-verilog-lint", "download it from https://github.com/chipsalliance/verible/releases"),
+                "verilator" => ("verilator", "install it with 'vpm install verilator'"),
+                other => anyhow::bail!("Unknown linter '{}'. Use 'verible' or 'verilator'.", other),
+            };
+            if which::which(binary).is_err() {
+                println!("Skipping {}: '{}' is not on PATH; {}.", tool, binary, hint);
+                continue;
+            }
+            ran += 1;
+            diagnostics.extend(match tool {
+                "verible" => run_verible(&sources)?,
+                _ => run_verilator(&sources)?,
+            });
+        }
+        anyhow::ensure!(ran > 0, "No linter is installed. Install Verilator with 'vpm install verilator' or put verible-verilog-lint on PATH.");
+
+        let mut waiver_files = get_lint_waivers();
+        waiver_files.extend(self.waivers.iter().cloned());
+        let waivers = load_waivers(&waiver_files)?;
+        let mut used = vec![false; waivers.len()];
+        diagnostics.retain(|diagnostic| match waivers.iter().position(|w| w.matches(diagnostic)) {
+            Some(index) => {
+                used[index] = true;
+                false
+            }
+            None => true,
+        });
+        let waived = used.iter().filter(|u| **u).count();
+        for (waiver, _) in waivers.iter().zip(&used).filter(|(_, used)| !**used) {
+            println!("Note: waiver {:?} from {} matched nothing", waiver.rule.as_deref().unwrap_or("*"), waiver.source);
+        }
+
+        diagnostics.sort_by(|a, b| (&a.file, a.line, a.column, a.severity).cmp(&(&b.file, b.line, b.column, b.severity)));
+        for diagnostic in &diagnostics {
+            println!("{}", diagnostic);
+        }
+        let errors = diagnostics.iter().filter(|d| d.severity == Severity::Error).count();
+        let warnings = diagnostics.len() - errors;
+        println!("{} errors, {} warnings ({} waivers applied)", errors, warnings, waived);
+
+        if errors > 0 || (self.deny_warnings && warnings > 0) {
+            return Err(SilentExit { code: 1 }.into());
+        }
+        Ok(())
+    }
+}
+
+impl Lint {
+    fn lint_sources(&self) -> Result<LintSources> {
+        let mut defines: Vec<(String, Option<String>)> = self.define.iter().map(|d| parse_define(d)).collect();
+        let mut include_dirs = Vec::new();
+        let top = match &self.top {
+            Some(top) => Some(find_module(top, &DesignIndex::load())?.1.name),
+            None => None,
+        };
+
+        let files = if !self.paths.is_empty() {
+            self.paths.iter().flat_map(|path| expand_path(Path::new(path))).collect()
+        } else if let Some(target) = &self.target {
+            let sources = select_sources(target)?;
+            include_dirs.extend(sources.include_dirs);
+            defines.extend(sources.defines);
+            sources.files
+        } else if let Some(top) = &top {
+            DesignIndex::load().dependency_files(top)
+        } else {
+            // The project's own code; vendored dependencies are linted where they come from.
+            project_sources().into_iter().filter(|p| !p.components().any(|c| c.as_os_str() == "vpm_modules")).collect()
+        };
+
+        for file in &files {
+            if let Some(dir) = file.parent().filter(|d| !d.as_os_str().is_empty()) {
+                if !include_dirs.iter().any(|d| d == dir) {
+                    include_dirs.push(dir.to_path_buf());
+                }
+            }
+        }
+        Ok(LintSources { files, include_dirs, defines, top })
+    }
+}
+
+/// A file, or every Verilog/SystemVerilog file below a directory.
+fn expand_path(path: &Path) -> Vec<PathBuf> {
+    if !path.is_dir() {
+        return vec![path.to_path_buf()];
+    }
+    let mut files: Vec<PathBuf> = walkdir::WalkDir::new(path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .map(|e| e.into_path())
+        .filter(|p| is_source(p))
+        .collect();
+    files.sort();
+    files
+}
+
+fn run_verible(sources: &LintSources) -> Result<Vec<Diagnostic>> {
+    let output = Command::new("verible-verilog-lint")
+        .args(["--lint_fatal=false", "--parse_fatal=false"])
+        .args(&sources.files)
+        .output()
+        .context("Failed to run verible-verilog-lint")?;
+    let text = String::from_utf8_lossy(&output.stdout).to_string() + &String::from_utf8_lossy(&output.stderr);
+
+    let mut diagnostics = parse_verible(&text);
+    diagnostics.extend(exit_failure("verible", output.status, &text, &diagnostics));
+    Ok(diagnostics)
+}
+
+fn parse_verible(text: &str) -> Vec<Diagnostic> {
+    // `path:line:col[-col]: message [Style: section] [rule]`, or a syntax error without a rule.
+    static LINE: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"^(?P<file>[^:]+):(?P<line>\d+):(?P<col>\d+)(?:-\d+)?:\s*(?P<message>.*?)(?:\s*\[Style:[^\]]*\])?(?:\s*\[(?P<rule>[\w-]+)\])?$").unwrap()
+    });
+    text.lines().filter_map(|line| LINE.captures(line)).map(|captures| {
+        let rule = captures.name("rule").map(|r| r.as_str().to_string());
+        Diagnostic {
+            file: normalize(&captures["file"]),
+            line: captures["line"].parse().unwrap_or(0),
+            column: captures["col"].parse().unwrap_or(0),
+            // Style violations carry a rule; anything else is a syntax error.
+            severity: if rule.is_some() { Severity::Warning } else { Severity::Error },
+            tool: "verible",
+            rule: rule.unwrap_or_else(|| "syntax".to_string()),
+            message: captures["message"].trim().to_string(),
+        }
+    }).collect()
+}
+
+fn run_verilator(sources: &LintSources) -> Result<Vec<Diagnostic>> {
+    let mut command = Command::new("verilator");
+    command.args(["--lint-only", "-Wall", "-Wno-fatal"]);
+    for dir in &sources.include_dirs {
+        command.arg(format!("-I{}", dir.display()));
+    }
+    for (name, value) in &sources.defines {
+        command.arg(match value {
+            Some(value) => format!("-D{}={}", name, value),
+            None => format!("-D{}", name),
+        });
+    }
+    if let Some(top) = &sources.top {
+        command.args(["--top-module", top]);
+    }
+    let output = command.args(&sources.files).output().context("Failed to run verilator")?;
+    let text = String::from_utf8_lossy(&output.stderr).to_string() + &String::from_utf8_lossy(&output.stdout);
+
+    let mut diagnostics = parse_verilator(&text);
+    diagnostics.extend(exit_failure("verilator", output.status, &text, &diagnostics));
+    Ok(diagnostics)
+}
+
+fn parse_verilator(text: &str) -> Vec<Diagnostic> {
+    // `%Warning-RULE: path:line:col: message` or `%Error[-RULE]: path:line:col: message`.
+    static LINE: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"^%(?P<severity>Warning|Error)(?:-(?P<rule>[A-Z0-9_]+))?: (?P<file>[^:\s]+):(?P<line>\d+):(?:(?P<col>\d+):)? (?P<message>.*)$").unwrap()
+    });
+    let mut diagnostics = Vec::new();
+    for line in text.lines() {
+        let Some(captures) = LINE.captures(line) else {
+            // Errors without a location, such as a missing module or the final error count.
+            if let Some(message) = line.strip_prefix("%Error") {
+                let message = message.split_once(": ").map_or(message, |(_, message)| message);
+                diagnostics.push(tool_error("verilator", "error", message.trim().to_string()));
+            }
+            continue;
+        };
+        let severity = if &captures["severity"] == "Error" { Severity::Error } else { Severity::Warning };
+        diagnostics.push(Diagnostic {
+            file: normalize(&captures["file"]),
+            line: captures["line"].parse().unwrap_or(0),
+            column: captures.name("col").and_then(|c| c.as_str().parse().ok()).unwrap_or(0),
+            severity,
+            tool: "verilator",
+            rule: captures.name("rule").map_or_else(|| if severity == Severity::Error { "error" } else { "warning" }.to_string(), |r| r.as_str().to_string()),
+            message: captures["message"].trim().to_string(),
+        });
+    }
+    diagnostics
+}
+
+/// An error the tool reported without a file and line.
+fn tool_error(tool: &'static str, rule: &str, message: String) -> Diagnostic {
+    Diagnostic { file: String::new(), line: 0, column: 0, severity: Severity::Error, tool, rule: rule.to_string(), message }
+}
+
+/// A failing exit status is an error unless the diagnostics already include one; the
+/// last line of output usually says what went wrong.
+fn exit_failure(tool: &'static str, status: ExitStatus, text: &str, diagnostics: &[Diagnostic]) -> Option<Diagnostic> {
+    if status.success() || diagnostics.iter().any(|d| d.severity == Severity::Error) {
+        return None;
+    }
+    let message = match text.lines().rev().map(str::trim).find(|line| !line.is_empty()) {
+        Some(last) => format!("failed with {}: {}", status, last),
+        None => format!("failed with {}", status),
+    };
+    Some(tool_error(tool, "exit", message))
+}
+
+/// Reports every file relative to the project, without a leading `./`.
+fn normalize(file: &str) -> String {
+    let path = Path::new(file);
+    let relative = std::env::current_dir().ok()
+        .and_then(|cwd| path.strip_prefix(cwd).ok().map(Path::to_path_buf))
+        .unwrap_or_else(|| path.to_path_buf());
+    relative.to_string_lossy().trim_start_matches("./").to_string()
+}
+
+/// Reads the `[[waiver]]` entries of each waiver file.
+fn load_waivers(files: &[String]) -> Result<Vec<Waiver>> {
+    let mut waivers = Vec::new();
+    for file in files {
+        let contents = fs::read_to_string(file).with_context(|| format!("Failed to read waiver file '{}'", file))?;
+        let doc = contents.parse::<DocumentMut>().with_context(|| format!("Failed to parse waiver file '{}'", file))?;
+        let Some(entries) = doc.get("waiver").and_then(|w| w.as_array_of_tables()) else { continue };
+        for entry in entries {
+            let field = |key: &str| entry.get(key).and_then(|v| v.as_str()).map(|s| s.to_string());
+            let line = match entry.get("line") {
+                Some(line) => Some(line.as_integer().and_then(|l| usize::try_from(l).ok()).filter(|l| *l > 0)
+                    .with_context(|| format!("Invalid line {} in waiver file '{}': expected a positive number", line.to_string().trim(), file))?),
+                None => None,
+            };
+            waivers.push(Waiver {
+                source: file.clone(),
+                tool: field("tool"),
+                rule: field("rule"),
+                file: field("file"),
+                line,
+                message: field("message"),
+            });
+        }
+    }
+    Ok(waivers)
+}
+
+/// Matches `text` against `pattern`, where `*` matches any run of characters and `?` any
+/// single character.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards_match_runs_and_single_characters() {
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("*", "rtl/fifo.sv"));
+        assert!(wildcard_match("rtl/*.sv", "rtl/fifo.sv"));
+        assert!(wildcard_match("rtl/fif?.sv", "rtl/fifo.sv"));
+        assert!(wildcard_match("*UNUSED*", "UNUSEDSIGNAL"));
+        assert!(!wildcard_match("rtl/fif?.sv", "rtl/fi.sv"));
+        assert!(!wildcard_match("rtl/*.sv", "rtl/fifo.v"));
+        assert!(!wildcard_match("UNUSED", "UNUSEDSIGNAL"));
+    }
+
+    #[test]
+    fn verible_lines() {
+        let diagnostics = parse_verible("\
+rtl/fifo.sv:12:5-9: Use spaces, not tabs. [Style: tabs] [no-tabs]
+rtl/fifo.sv:30:1: syntax error at token \"endmodule\"
+Some other output
+");
+        assert_eq!(diagnostics.len(), 2);
+        let style = &diagnostics[0];
+        assert_eq!((style.file.as_str(), style.line, style.column), ("rtl/fifo.sv", 12, 5));
+        assert_eq!((style.severity, style.rule.as_str(), style.message.as_str()), (Severity::Warning, "no-tabs", "Use spaces, not tabs."));
+        let syntax = &diagnostics[1];
+        assert_eq!((syntax.line, syntax.column, syntax.severity, syntax.rule.as_str()), (30, 1, Severity::Error, "syntax"));
+        assert_eq!(syntax.message, "syntax error at token \"endmodule\"");
+    }
+
+    #[test]
+    fn verilator_lines() {
+        let diagnostics = parse_verilator("\
+%Warning-UNUSEDSIGNAL: rtl/fifo.sv:7:15: Signal is not used: 'spare'
+                                        : ... note: In instance 'fifo'
+%Error: rtl/top.sv:4: syntax error, unexpected endmodule
+%Error: Exiting due to 1 error(s)
+");
+        assert_eq!(diagnostics.len(), 3);
+        let warning = &diagnostics[0];
+        assert_eq!((warning.file.as_str(), warning.line, warning.column), ("rtl/fifo.sv", 7, 15));
+        assert_eq!((warning.severity, warning.rule.as_str(), warning.message.as_str()), (Severity::Warning, "UNUSEDSIGNAL", "Signal is not used: 'spare'"));
+        let error = &diagnostics[1];
+        assert_eq!((error.file.as_str(), error.line, error.column), ("rtl/top.sv", 4, 0));
+        assert_eq!((error.severity, error.rule.as_str()), (Severity::Error, "error"));
+        // Errors without a location are reported for the tool as a whole.
+        let summary = &diagnostics[2];
+        assert_eq!((summary.file.as_str(), summary.severity, summary.message.as_str()), ("", Severity::Error, "Exiting due to 1 error(s)"));
+    }
+
+    #[test]
+    fn waivers_match_with_wildcards() {
+        let diagnostic = &parse_verilator("%Warning-UNUSEDSIGNAL: rtl/fifo.sv:7:15: Signal is not used: 'spare'")[0];
+        let waiver = |rule: &str, file: &str| Waiver { rule: Some(rule.to_string()), file: Some(file.to_string()), ..Waiver::default() };
+        assert!(waiver("UNUSED*", "./rtl/fif?.sv").matches(diagnostic));
+        assert!(waiver("*", "*").matches(diagnostic));
+        assert!(!waiver("UNDRIVEN*", "rtl/*").matches(diagnostic));
+        assert!(!waiver("UNUSED*", "rtl/fif?.v").matches(diagnostic));
+        assert!(!Waiver { line: Some(8), ..Waiver::default() }.matches(diagnostic));
+    }
+
+    #[test]
+    fn waivers_with_a_negative_line_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("waivers.toml");
+        fs::write(&file, "[[waiver]]\nrule = \"UNUSED*\"\nline = -3\n").unwrap();
+        let error = load_waivers(&[file.to_string_lossy().into_owned()]).unwrap_err();
+        assert!(error.to_string().contains("Invalid line -3"));
+    }
+}
@@ -1,13 +1,37 @@
-use anyhow::{Result, Context, anyhow};
-use std::collections::HashSet;
-use std::process::Command;
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use toml_edit::DocumentMut;
+
 use crate::cmd::{Execute, List};
-use tempfile::tempdir;
+use crate::cmd::search::load_catalog;
+use crate::git::strip_credentials;
+use crate::lockfile::read_lockfile;
+use crate::registry::sha256_checksum;
+use crate::toml::{get_registry_dependencies, get_top_modules};
 
-const STD_LIB_URL: &str = "https://github.com/getinstachip/openchips";
+/// A dependency of the current project together with the state of its vendored files.
+#[derive(Serialize, Debug)]
+struct InstalledDependency {
+    source: String,
+    kind: String,
+    version: Option<String>,
+    commits: Vec<String>,
+    top_modules: Vec<String>,
+    vendored_files: usize,
+    modified_files: Vec<String>,
+    missing_files: Vec<String>,
+    status: String,
+    license: String,
+}
 
 impl Execute for List {
     async fn execute(&self) -> Result<()> {
+        if self.installed {
+            return list_installed(self.json);
+        }
         match list_verilog_files() {
             Ok(verilog_files) => {
                 println!("Available Verilog modules:");
@@ -20,8 +44,7 @@ impl Execute for List {
                 eprintln!("Error: Failed to list Verilog files. {}", e);
                 eprintln!("Debug steps:");
                 eprintln!("1. Check your internet connection");
-                eprintln!("2. Ensure git is installed and accessible from the command line");
-                eprintln!("3. Verify you have read permissions for the temporary directory");
+                eprintln!("2. Verify you have write permissions for the vpm cache directory");
                 Err(e)
             }
         }
@@ -29,52 +52,154 @@ impl Execute for List {
 }
 
 fn list_verilog_files() -> Result<Vec<String>> {
-    let temp_dir = tempdir().context("Failed to create temporary directory. Ensure you have write permissions in the system temp directory.")?;
-    let repo_path = temp_dir.path();
+    let catalog = load_catalog(false)?;
+    // Modules are listed by file name, as they are given to `vpm include`.
+    let mut verilog_files: Vec<String> = catalog.entries.iter()
+        .filter_map(|entry| Path::new(&entry.path).file_stem())
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .collect();
+    verilog_files.sort();
+    verilog_files.dedup();
 
-    // Clone the repository
-    let output = Command::new("git")
-        .args([
-            "clone",
-            "--depth",
-            "1",
-            "--single-branch",
-            "--jobs",
-            "4",
-            STD_LIB_URL,
-            repo_path.to_str().unwrap_or_default(),
-        ])
-        .output()
-        .context("Failed to execute git command. Ensure git is installed and accessible from the command line.")?;
+    if verilog_files.is_empty() {
+        Err(anyhow!("No Verilog files found in the repository. This could indicate an issue with the repository structure or content."))
+    } else {
+        Ok(verilog_files)
+    }
+}
 
-    if !output.status.success() {
-        return Err(anyhow!(
-            "Git clone failed. Error: {}. This could be due to network issues, incorrect repository URL, or git configuration problems.",
-            String::from_utf8_lossy(&output.stderr)
-        ));
+fn list_installed(json: bool) -> Result<()> {
+    let dependencies = installed_dependencies()?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&dependencies)?);
+        return Ok(());
+    }
+    if dependencies.is_empty() {
+        println!("No dependencies found in vpm.toml.");
+        return Ok(());
     }
 
-    let mut verilog_files = HashSet::new();
+    println!("Installed dependencies:");
+    for dependency in &dependencies {
+        match &dependency.version {
+            Some(version) => println!("  {}@{}", dependency.source, version),
+            None => println!("  {} @ {}", dependency.source, dependency.commits.iter().map(|c| &c[..c.len().min(7)]).collect::<Vec<_>>().join(", ")),
+        }
+        println!("    Top modules: {}", dependency.top_modules.join(", "));
+        println!("    Files: {} vendored, {}", dependency.vendored_files, dependency.status);
+        for file in &dependency.modified_files {
+            println!("      modified: {}", file);
+        }
+        for file in &dependency.missing_files {
+            println!("      missing:  {}", file);
+        }
+        println!("    License: {}", dependency.license);
+    }
+    Ok(())
+}
 
-    for entry in walkdir::WalkDir::new(repo_path)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        if let Some(extension) = entry.path().extension() {
-            match extension.to_str() {
-                Some("v") | Some("sv") => {
-                    if let Some(file_name) = entry.path().file_stem() {
-                        verilog_files.insert(file_name.to_string_lossy().into_owned());
+fn installed_dependencies() -> Result<Vec<InstalledDependency>> {
+    let lock_entries = read_lockfile()?;
+    let mut dependencies = Vec::new();
+
+    let mut repos: BTreeMap<String, (Vec<String>, Vec<String>)> = BTreeMap::new();
+    for (repo_link, top_module, commit_hash) in get_top_modules() {
+        let (top_modules, commits) = repos.entry(repo_link).or_default();
+        let name = Path::new(&top_module).file_name().and_then(|f| f.to_str()).unwrap_or(&top_module).to_string();
+        if !top_modules.contains(&name) {
+            top_modules.push(name);
+        }
+        if !commits.contains(&commit_hash) {
+            commits.push(commit_hash);
+        }
+    }
+
+    for (repo_link, (top_modules, commits)) in repos {
+        let source = strip_credentials(&repo_link);
+        let (mut vendored, mut modified, mut missing, mut verified) = (Vec::new(), Vec::new(), Vec::new(), 0);
+        for entry in lock_entries.iter().filter(|e| e.source == source) {
+            let path = Path::new(&entry.full_path);
+            match (&entry.checksum, fs::read(path)) {
+                (Some(checksum), Ok(contents)) => {
+                    verified += 1;
+                    if &sha256_checksum(&contents) != checksum {
+                        modified.push(entry.full_path.clone());
                     }
+                    vendored.push(path.to_path_buf());
                 }
-                _ => {}
+                (Some(_), Err(_)) => missing.push(entry.full_path.clone()),
+                // Entries without a checksum were written by older versions of vpm, or are
+                // submodules that were referenced but never vendored.
+                (None, Ok(_)) => vendored.push(path.to_path_buf()),
+                (None, Err(_)) => {}
             }
         }
+        let status = if !missing.is_empty() {
+            "missing files"
+        } else if !modified.is_empty() {
+            "modified"
+        } else if verified < vendored.len() {
+            "unverified"
+        } else {
+            "clean"
+        };
+        dependencies.push(InstalledDependency {
+            source,
+            kind: "git".to_string(),
+            version: None,
+            commits,
+            top_modules,
+            vendored_files: vendored.len() + missing.len(),
+            modified_files: modified,
+            missing_files: missing,
+            status: status.to_string(),
+            license: vendored.iter().find_map(|p| spdx_license(p)).unwrap_or_else(|| "unknown".to_string()),
+        });
     }
 
-    if verilog_files.is_empty() {
-        Err(anyhow!("No Verilog files found in the repository. This could indicate an issue with the repository structure or content."))
-    } else {
-        Ok(verilog_files.into_iter().collect())
+    for (name, version, top_modules) in get_registry_dependencies() {
+        let package_dir = Path::new("vpm_modules").join(&name);
+        let files: Vec<_> = walkdir::WalkDir::new(&package_dir).into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.into_path())
+            .collect();
+        dependencies.push(InstalledDependency {
+            source: name,
+            kind: "registry".to_string(),
+            version: Some(version),
+            commits: Vec::new(),
+            top_modules,
+            vendored_files: files.len(),
+            modified_files: Vec::new(),
+            missing_files: Vec::new(),
+            // Registry archives are verified as a whole on install; individual files are not tracked.
+            status: if files.is_empty() { "missing files" } else { "unverified" }.to_string(),
+            license: package_license(&package_dir)
+                .or_else(|| files.iter().find_map(|p| spdx_license(p)))
+                .unwrap_or_else(|| "unknown".to_string()),
+        });
     }
+
+    Ok(dependencies)
+}
+
+/// Reads the license of an installed registry package from its vpm.toml or LICENSE file.
+fn package_license(package_dir: &Path) -> Option<String> {
+    let from_manifest = fs::read_to_string(package_dir.join("vpm.toml")).ok()
+        .and_then(|c| c.parse::<DocumentMut>().ok())
+        .and_then(|doc| doc.get("package")?.get("license")?.as_str().map(|s| s.to_string()));
+    from_manifest.or_else(|| {
+        fs::read_dir(package_dir).ok()?
+            .filter_map(|e| e.ok())
+            .find(|e| e.file_name().to_string_lossy().to_uppercase().starts_with("LICENSE"))
+            .and_then(|e| fs::read_to_string(e.path()).ok())
+            .and_then(|c| c.lines().map(|l| l.trim()).find(|l| !l.is_empty()).map(|l| l.to_string()))
+    })
+}
+
+fn spdx_license(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok()?
+        .lines()
+        .find_map(|line| line.split_once("SPDX-License-Identifier:").map(|(_, id)| id.trim().trim_end_matches("*/").trim().to_string()))
 }
@@ -2,9 +2,19 @@ mod cmd;
 mod upgrade;
 mod include;
 mod update;
+mod publish;
+mod sync;
+mod vendor;
+mod generate;
+mod adopt;
 mod remove;
 mod dotf;
 mod list;
+mod search;
+mod info;
+mod graph;
+mod lint;
+mod export;
 mod install;
 mod sim;
 mod docs;
@@ -37,11 +47,36 @@ impl Execute for Cmd {
                 send_event("include".to_string()).await?;
                 Ok(())
             },
+            Cmd::Publish(cmd) => {
+                cmd.execute().await?;
+                send_event("publish".to_string()).await?;
+                Ok(())
+            },
             Cmd::Update(cmd) => {
                 cmd.execute().await?;
                 send_event("update".to_string()).await?;
                 Ok(())
             },
+            Cmd::Sync(cmd) => {
+                cmd.execute().await?;
+                send_event("sync".to_string()).await?;
+                Ok(())
+            },
+            Cmd::Vendor(cmd) => {
+                cmd.execute().await?;
+                send_event("vendor".to_string()).await?;
+                Ok(())
+            },
+            Cmd::Generate(cmd) => {
+                cmd.execute().await?;
+                send_event("generate".to_string()).await?;
+                Ok(())
+            },
+            Cmd::Adopt(cmd) => {
+                cmd.execute().await?;
+                send_event("adopt".to_string()).await?;
+                Ok(())
+            },
             Cmd::Remove(cmd) => {
                 cmd.execute().await?;
                 send_event("remove".to_string()).await?;
@@ -62,6 +97,31 @@ impl Execute for Cmd {
                 send_event("list".to_string()).await?;
                 Ok(())
             },
+            Cmd::Search(cmd) => {
+                cmd.execute().await?;
+                send_event("search".to_string()).await?;
+                Ok(())
+            },
+            Cmd::Info(cmd) => {
+                cmd.execute().await?;
+                send_event("info".to_string()).await?;
+                Ok(())
+            },
+            Cmd::Graph(cmd) => {
+                cmd.execute().await?;
+                send_event("graph".to_string()).await?;
+                Ok(())
+            },
+            Cmd::Lint(cmd) => {
+                cmd.execute().await?;
+                send_event("lint".to_string()).await?;
+                Ok(())
+            },
+            Cmd::Export(cmd) => {
+                cmd.execute().await?;
+                send_event("export".to_string()).await?;
+                Ok(())
+            },
             Cmd::Sim(cmd) => {
                 cmd.execute().await?;
                 send_event("sim".to_string()).await?;
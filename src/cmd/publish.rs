@@ -0,0 +1,84 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::env::current_dir;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::cmd::{Execute, Publish};
+use crate::config_man::get_registry_index;
+use crate::design::{is_rtl, is_source, parse_file};
+use crate::registry::{publish, PackageVersion};
+use crate::toml::get_package;
+
+impl Execute for Publish {
+    async fn execute(&self) -> Result<()> {
+        let package = get_package();
+        let index = self.index.clone().unwrap_or_else(get_registry_index);
+        let index = index.strip_prefix("file://").unwrap_or(&index);
+        anyhow::ensure!(
+            !index.contains("://") && !index.starts_with("git@"),
+            "'vpm publish' only supports local or file-based registry indexes. Publish to a local clone of '{}' and push it instead.", index
+        );
+
+        let root = current_dir()?;
+        let files = collect_package_files(&root);
+        let top_modules = find_top_modules(&root, &files)?;
+        anyhow::ensure!(!top_modules.is_empty(), "No Verilog or SystemVerilog modules found to publish");
+
+        println!("Publishing {}@{} ({} files)", package.name, package.version, files.len());
+        let published = publish(
+            Path::new(index),
+            &PackageVersion {
+                name: package.name.clone(),
+                version: package.version.clone(),
+                source: String::new(),
+                checksum: String::new(),
+                top_modules,
+            },
+            &package.description,
+            &package.license,
+            &root,
+            &files,
+        )?;
+
+        println!("Published {}@{} to {}", published.name, published.version, index);
+        println!("  Archive: {}", Path::new(index).join(&published.source).display());
+        println!("  Checksum: {}", published.checksum);
+        println!("  Top modules: {}", published.top_modules.join(", "));
+        Ok(())
+    }
+}
+
+/// Collects the manifest, lockfile, license/readme and RTL sources of the project,
+/// skipping hidden directories and vendored dependencies.
+fn collect_package_files(root: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || !(e.file_name().to_string_lossy().starts_with('.') || e.file_name() == "vpm_modules" || e.file_name() == "target"))
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| {
+            let name = e.file_name().to_string_lossy();
+            is_rtl(e.path())
+                || (e.depth() == 1 && (name == "vpm.toml" || name == "vpm.lock" || name.starts_with("LICENSE") || name.starts_with("README")))
+        })
+        .filter_map(|e| e.path().strip_prefix(root).ok().map(|p| p.to_path_buf()))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Modules declared in the package that no other module in the package instantiates.
+fn find_top_modules(root: &Path, files: &[PathBuf]) -> Result<Vec<String>> {
+    let mut declared = BTreeSet::new();
+    let mut instantiated = BTreeSet::new();
+    // Headers are only meaningful where they are included, so only sources are parsed.
+    for file in files.iter().filter(|f| is_source(f)) {
+        let modules = parse_file(&root.join(file)).with_context(|| format!("Failed to parse '{}'", file.display()))?;
+        for module in modules {
+            declared.insert(module.name);
+            instantiated.extend(module.submodules);
+        }
+    }
+    Ok(declared.difference(&instantiated).cloned().collect())
+}
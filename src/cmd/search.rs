@@ -0,0 +1,184 @@
+use anyhow::{Context, Result};
+use fuzzy_matcher::FuzzyMatcher;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cmd::{Execute, Search};
+use crate::cmd::sim::extract_module_info;
+use crate::config_man::cache_dir;
+use crate::design::is_source;
+use crate::git::{changed_paths, get_remote_head, list_repo_blobs, materialize_files, update_repo};
+
+pub const STD_LIB_URL: &str = "https://github.com/getinstachip/openchips";
+
+/// A module available in the standard library, as recorded in the cached catalog.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CatalogEntry {
+    pub name: String,
+    pub description: String,
+    pub category: String,
+    pub license: String,
+    pub repo: String,
+    pub path: String,
+    pub ports: Vec<String>,
+    pub parameters: Vec<String>,
+    pub blob: String,
+}
+
+/// Locally cached index of the standard library, keyed by the commit it was built from.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Catalog {
+    pub commit: String,
+    pub entries: Vec<CatalogEntry>,
+}
+
+impl Execute for Search {
+    async fn execute(&self) -> Result<()> {
+        let catalog = load_catalog(self.offline)?;
+        let matcher = SkimMatcherV2::default();
+        let query = self.query.as_deref().unwrap_or_default();
+
+        let mut results: Vec<(i64, &CatalogEntry)> = catalog.entries.iter()
+            .filter(|e| self.category.as_ref().is_none_or(|c| e.category.eq_ignore_ascii_case(c)))
+            .filter(|e| self.license.as_ref().is_none_or(|l| e.license.to_lowercase().contains(&l.to_lowercase())))
+            .filter_map(|e| {
+                if query.is_empty() {
+                    Some((0, e))
+                } else {
+                    matcher.fuzzy_match(&format!("{} {}", e.name, e.description), query).map(|score| (score, e))
+                }
+            })
+            .collect();
+        results.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.name.cmp(&b.1.name)));
+
+        if results.is_empty() {
+            println!("No modules found matching '{}'", query);
+            return Ok(());
+        }
+        for (_, entry) in results {
+            println!("{} [{}] ({})", entry.name, entry.category, if entry.license.is_empty() { "unknown license" } else { &entry.license });
+            if !entry.description.is_empty() {
+                println!("    {}", entry.description);
+            }
+            println!("    {}/blob/{}/{}", entry.repo, catalog.commit, entry.path);
+            if !entry.parameters.is_empty() {
+                println!("    Parameters: {}", entry.parameters.join(", "));
+            }
+            if !entry.ports.is_empty() {
+                println!("    Ports: {}", entry.ports.join(", "));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn catalog_dir() -> Result<PathBuf> {
+    Ok(cache_dir()?.join("catalog"))
+}
+
+fn read_cached_catalog() -> Option<Catalog> {
+    let contents = fs::read_to_string(catalog_dir().ok()?.join("catalog.json")).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// Returns the module catalog, refreshing it from the standard library unless `offline`
+/// is set. Falls back to the cached catalog when the refresh fails.
+pub fn load_catalog(offline: bool) -> Result<Catalog> {
+    let cached = read_cached_catalog();
+    if offline {
+        return cached.context("No cached module index found. Run 'vpm search' once while online to build it.");
+    }
+    match refresh_catalog(cached.clone().unwrap_or_default()) {
+        Ok(catalog) => {
+            let dir = catalog_dir()?;
+            fs::create_dir_all(&dir)?;
+            fs::write(dir.join("catalog.json"), serde_json::to_string_pretty(&catalog)?)?;
+            Ok(catalog)
+        }
+        Err(e) => match cached {
+            Some(catalog) => {
+                eprintln!("Warning: Failed to refresh the module index ({}). Using the cached index.", e);
+                Ok(catalog)
+            }
+            None => Err(e),
+        },
+    }
+}
+
+/// Rebuilds the catalog for the latest standard library commit. The cached clone is
+/// moved to that commit and only files that changed since `cached` was built are parsed.
+fn refresh_catalog(cached: Catalog) -> Result<Catalog> {
+    let head = get_remote_head(STD_LIB_URL)?;
+    if head == cached.commit {
+        return Ok(cached);
+    }
+
+    let repo_path = catalog_dir()?.join("openchips");
+    let previous = update_repo(STD_LIB_URL, &repo_path, &head)?;
+    let changed = previous.as_deref()
+        .filter(|previous| *previous == cached.commit)
+        .and_then(|previous| changed_paths(&repo_path, previous, &head).ok());
+    let blobs = list_repo_blobs(&repo_path)?;
+
+    let license = blobs.iter()
+        .find(|(path, _)| !path.contains('/') && path.to_uppercase().starts_with("LICENSE"))
+        .and_then(|(path, _)| {
+            let file = repo_path.join(path);
+            materialize_files(&repo_path, std::slice::from_ref(&file)).ok()?;
+            fs::read_to_string(file).ok()?.lines().map(|l| l.trim()).find(|l| !l.is_empty()).map(|l| l.to_string())
+        })
+        .unwrap_or_default();
+
+    let mut entries = Vec::new();
+    for (path, blob) in blobs.iter().filter(|(path, _)| is_source(Path::new(path))) {
+        let unchanged = changed.as_ref().is_some_and(|changed| !changed.contains(path));
+        if let Some(entry) = cached.entries.iter().find(|e| &e.path == path && (unchanged || &e.blob == blob)) {
+            entries.push(entry.clone());
+            continue;
+        }
+        let file = repo_path.join(path);
+        materialize_files(&repo_path, std::slice::from_ref(&file))?;
+        match parse_entry(&file, path, blob, &license) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => eprintln!("Warning: Skipping '{}': {}", path, e),
+        }
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(Catalog { commit: head, entries })
+}
+
+fn parse_entry(file: &Path, path: &str, blob: &str, repo_license: &str) -> Result<CatalogEntry> {
+    let contents = fs::read_to_string(file)?;
+    let (name, ports, parameters) = extract_module_info(file.to_str().unwrap_or_default())?;
+    let comments: Vec<&str> = contents.lines()
+        .map(|l| l.trim())
+        .take_while(|l| l.is_empty() || l.starts_with("//"))
+        .map(|l| l.trim_start_matches('/').trim())
+        .filter(|l| !l.is_empty())
+        .collect();
+    let license = comments.iter()
+        .find_map(|l| l.strip_prefix("SPDX-License-Identifier:"))
+        .map(|l| l.trim().to_string())
+        .unwrap_or_else(|| repo_license.to_string());
+    let description = comments.iter()
+        .find(|l| !l.starts_with("SPDX-License-Identifier:"))
+        .map(|l| l.to_string())
+        .unwrap_or_default();
+
+    Ok(CatalogEntry {
+        name,
+        description,
+        category: path.split_once('/').map(|(dir, _)| dir.to_string()).unwrap_or_default(),
+        license,
+        repo: STD_LIB_URL.to_string(),
+        path: path.to_string(),
+        ports: ports.into_iter()
+            .map(|(direction, width, name)| [Some(direction), width, Some(name)].into_iter().flatten().collect::<Vec<_>>().join(" "))
+            .collect(),
+        parameters: parameters.into_iter().map(|(name, value)| format!("{} = {}", name, value.trim())).collect(),
+        blob: blob.to_string(),
+    })
+}
@@ -5,21 +5,33 @@ use std::path::{Path, PathBuf};
 use std::fs;
 use fastrand;
 use crate::cmd::{Execute, Sim};
+use crate::design::parse_file;
+use crate::targets::select_sources;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use fancy_regex::Regex;
 use walkdir::WalkDir;
 
 impl Execute for Sim {
     async fn execute(&self) -> Result<()> {
+        let target_sources = self.target.as_deref().map(select_sources).transpose()?;
         let output_path = if let Some(folder) = &self.folder {
             compile_verilog_from_folder(folder)?
         } else {
             let mut verilog_files = self.verilog_files.clone();
+            let mut extra_args = Vec::new();
+            if let Some(sources) = &target_sources {
+                for file in &sources.files {
+                    let file = file.to_string_lossy().into_owned();
+                    if !verilog_files.contains(&file) {
+                        verilog_files.push(file);
+                    }
+                }
+                extra_args = sources.include_and_define_args();
+            }
             if !testbench_exists(&verilog_files) {
                 generate_and_add_testbench(&mut verilog_files)?;
             }
-            compile_verilog(&verilog_files)?
+            compile_verilog(&verilog_files, &extra_args)?
         };
 
         if self.waveform {
@@ -67,7 +79,7 @@ pub fn compile_verilog_from_folder(folder: &str) -> Result<PathBuf> {
     let random_output_name = generate_random_output_name();
     let output_path = output_dir.join(&random_output_name);
 
-    let command_status = run_iverilog_command(output_path.to_str().unwrap(), &verilog_files)?;
+    let command_status = run_iverilog_command(output_path.to_str().unwrap(), &verilog_files, &[])?;
 
     if !command_status.success() {
         return Err(anyhow::anyhow!("Compilation failed. Please check the error messages above."));
@@ -152,67 +164,19 @@ pub fn generate_testbench(module_path: &str, is_systemverilog: bool) -> Result<S
     Ok(testbench)
 }
 
-fn extract_module_info(module_path: &str) -> Result<(String, Vec<(String, Option<String>, String)>, Vec<(String, String)>)> {
-    let file = File::open(module_path)
-        .context(format!("Failed to open module file: {}. Please check if the file exists and you have read permissions.", module_path))?;
-    let reader = BufReader::new(file);
-
-    let mut module_name = String::new();
-    let mut ports = Vec::new();
-    let mut parameters = Vec::new();
-    let mut in_module = false;
-    let module_regex = Regex::new(r"module\s+(\w+)\s*(?:\(|#)").unwrap();
-    let port_regex = Regex::new(r"(input|output|inout)\s+(?:reg|wire|logic)?\s*(\[.*?\])?\s*(\w+)").unwrap();
-    let parameter_regex = Regex::new(r"parameter\s+(\w+)\s*=\s*([^,\)]+)").unwrap();
-    let inline_parameter_regex = Regex::new(r"(\w+)\s*=\s*([^,\)]+)").unwrap();
-
-    for line in reader.lines() {
-        let line = line?;
-        if !in_module {
-            if let Ok(Some(captures)) = module_regex.captures(&line) {
-                module_name = captures[1].to_string();
-                in_module = true;
-            }
-        } else {
-            for capture_result in port_regex.captures_iter(&line) {
-                if let Ok(capture) = capture_result {
-                    let direction = capture[1].to_string();
-                    let bus_width = capture.get(2).map(|m| m.as_str().to_string());
-                    let name = capture[3].to_string();
-                    ports.push((direction, bus_width, name));
-                }
-            }
-            for capture_result in parameter_regex.captures_iter(&line) {
-                if let Ok(capture) = capture_result {
-                    let name = capture[1].to_string();
-                    let value = capture[2].to_string();
-                    if let Some(existing) = parameters.iter_mut().find(|(n, _)| n == &name) {
-                        existing.1 = value;
-                    } else {
-                        parameters.push((name, value));
-                    }
-                }
-            }
-            for capture_result in inline_parameter_regex.captures_iter(&line) {
-                if let Ok(capture) = capture_result {
-                    let name = capture[1].to_string();
-                    let value = capture[2].to_string();
-                    if !parameters.iter().any(|(n, _)| n == &name) {
-                        parameters.push((name, value));
-                    }
-                }
-            }
-            if line.contains(");") {
-                break;
-            }
-        }
-    }
-
-    if module_name.is_empty() {
-        return Err(anyhow::anyhow!("Could not find module declaration in {}. Please ensure the file contains a valid Verilog or SystemVerilog module.", module_path));
-    }
-
-    Ok((module_name, ports, parameters))
+/// Returns the name, ports and parameters of the module a file is named after, or of the
+/// first module it declares.
+pub fn extract_module_info(module_path: &str) -> Result<(String, Vec<(String, Option<String>, String)>, Vec<(String, String)>)> {
+    let modules = parse_file(Path::new(module_path))
+        .context(format!("Failed to read module file: {}. Please check if the file exists and you have read permissions.", module_path))?;
+    let stem = Path::new(module_path).file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    let module = modules.iter().find(|m| m.name == stem).or(modules.first())
+        .ok_or_else(|| anyhow::anyhow!("Could not find module declaration in {}. Please ensure the file contains a valid Verilog or SystemVerilog module.", module_path))?;
+
+    let parameters = module.parameters.iter()
+        .map(|(name, default)| (name.clone(), default.clone().unwrap_or_default()))
+        .collect();
+    Ok((module.name.clone(), module.ports.clone(), parameters))
 }
 
 fn generate_testbench_header(module_name: &str) -> String {
@@ -339,14 +303,14 @@ fn remove_comments_from_file(file_path: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn compile_verilog(verilog_files: &Vec<String>) -> Result<PathBuf> {
+pub fn compile_verilog(verilog_files: &Vec<String>, extra_args: &[String]) -> Result<PathBuf> {
     println!("Compiling Verilog files...");
 
     let first_file = &verilog_files[0];
     let output_dir = Path::new(first_file).parent().unwrap();
     let random_output_name = generate_random_output_name();
     let output_path = output_dir.join(&random_output_name);
-    let command_status = run_iverilog_command(output_path.to_str().unwrap(), verilog_files)?;
+    let command_status = run_iverilog_command(output_path.to_str().unwrap(), verilog_files, extra_args)?;
 
     if !command_status.success() {
         return Err(anyhow::anyhow!("Failed to compile Verilog files. Please check your Verilog code for syntax errors."));
@@ -365,9 +329,10 @@ fn generate_random_output_name() -> String {
         .collect()
 }
 
-fn run_iverilog_command(output_name: &str, verilog_files: &[String]) -> Result<std::process::ExitStatus> {
+fn run_iverilog_command(output_name: &str, verilog_files: &[String], extra_args: &[String]) -> Result<std::process::ExitStatus> {
     let mut command = Command::new("iverilog");
     command.arg("-o").arg(output_name);
+    command.args(extra_args);
     for file in verilog_files {
         command.arg(file);
     }
@@ -0,0 +1,118 @@
+use anyhow::{Context, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+
+use crate::cmd::{Execute, Sync};
+use crate::cmd::generate::run_generator;
+use crate::cmd::include::{include_from_registry, include_module_from_repo, IncludeOptions};
+use crate::git::name_from_url;
+use crate::toml::{get_generators, get_registry_dependencies, get_top_modules};
+use crate::vendor::{fetch_repository, open_archive};
+
+impl Execute for Sync {
+    async fn execute(&self) -> Result<()> {
+        let archive = self.archive.as_ref().map(|path| open_archive(Path::new(path))).transpose()?;
+        if let Some(archive) = &archive {
+            archive.restore_manifests()?;
+        }
+        // Shared with the fetch tasks, which run on other threads.
+        let options = Arc::new(IncludeOptions { archive, ..IncludeOptions::default() });
+        let top_modules = get_top_modules();
+        let registry_packages = get_registry_dependencies();
+        let generators = get_generators();
+        if top_modules.is_empty() && registry_packages.is_empty() && generators.is_empty() {
+            println!("No dependencies found in vpm.toml. Nothing to sync.");
+            return Ok(());
+        }
+
+        for (name, version, _) in &registry_packages {
+            include_from_registry(&format!("{}@{}", name, version), &options).await?;
+        }
+
+        if !top_modules.is_empty() {
+            let jobs = self.jobs.unwrap_or_else(|| std::thread::available_parallelism().map_or(4, |n| n.get())).max(1);
+            let mut sources: Vec<(String, String)> = top_modules.iter()
+                .map(|(repo_link, _, commit_hash)| (repo_link.clone(), commit_hash.clone()))
+                .collect();
+            sources.sort();
+            sources.dedup();
+
+            let fetched = fetch_repositories(&sources, jobs, &options).await?;
+            include_modules(&top_modules, &fetched, &options)?;
+
+            for (_, _, package_name, _) in &fetched {
+                let _ = fs::remove_dir_all(PathBuf::from("/tmp").join(package_name));
+            }
+            println!("Synced {} module(s) from {} repositories.", top_modules.len(), fetched.len());
+        }
+
+        for generator in &generators {
+            run_generator(generator, false, &options)?;
+        }
+        if let Some(archive) = &options.archive {
+            archive.apply_patches()?;
+        }
+        Ok(())
+    }
+}
+
+/// Vendors every top module from its fetched repository, one at a time and in vpm.toml
+/// order, so the output is deterministic and modules sharing submodules do not write the
+/// same files at once.
+fn include_modules(top_modules: &[(String, String, String)], fetched: &[(String, String, String, String)], options: &IncludeOptions) -> Result<()> {
+    for (repo_link, top_module, commit_hash) in top_modules {
+        let (_, _, package_name, commit) = fetched.iter()
+            .find(|(url, commit, _, _)| url == repo_link && commit == commit_hash)
+            .context("Repository was not fetched")?;
+        let module_file = Path::new(top_module).file_name().and_then(|f| f.to_str()).unwrap_or(top_module);
+        println!("Syncing module '{}' from {}@{}", module_file, repo_link, commit);
+        include_module_from_repo(package_name, module_file, repo_link, false, commit, options)
+            .with_context(|| format!("Failed to sync module '{}' from {}", module_file, repo_link))?;
+    }
+    Ok(())
+}
+
+/// Fetches every `(repo_link, commit_hash)` source with at most `jobs` fetches in flight.
+/// Returns `(repo_link, commit_hash, package_name, resolved_commit)` in the order of `sources`.
+async fn fetch_repositories(sources: &[(String, String)], jobs: usize, options: &Arc<IncludeOptions>) -> Result<Vec<(String, String, String, String)>> {
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(ProgressStyle::default_spinner().template("{spinner} {msg}").unwrap());
+    pb.set_message(format!("Fetching {} repositories ({} jobs)...", sources.len(), jobs));
+    pb.enable_steady_tick(Duration::from_millis(100));
+    let start = Instant::now();
+
+    let semaphore = Arc::new(Semaphore::new(jobs));
+    let handles: Vec<_> = sources.iter().cloned().map(|(repo_link, commit_hash)| {
+        let semaphore = semaphore.clone();
+        let options = options.clone();
+        tokio::spawn(async move {
+            let _permit = semaphore.acquire_owned().await?;
+            tokio::task::spawn_blocking(move || {
+                let started = Instant::now();
+                let short_hash = &commit_hash[..commit_hash.len().min(7)];
+                let package_name = format!("{}-{}", name_from_url(&repo_link), if short_hash.is_empty() { "HEAD" } else { short_hash });
+                let commit = fetch_repository(options.archive.as_ref(), &repo_link, &PathBuf::from("/tmp").join(&package_name), Some(commit_hash.as_str()).filter(|c| !c.is_empty()), true, None)
+                    .with_context(|| format!("Failed to fetch '{}'", repo_link))?;
+                Ok::<_, anyhow::Error>((repo_link, commit_hash, package_name, commit, started.elapsed()))
+            }).await?
+        })
+    }).collect();
+
+    // Awaiting in submission order keeps the report deterministic regardless of which fetch finishes first.
+    let mut fetched = Vec::new();
+    for handle in handles {
+        fetched.push(handle.await??);
+    }
+    pb.finish_with_message(format!("Fetched {} repositories ({:.2?})", fetched.len(), start.elapsed()));
+
+    Ok(fetched.into_iter()
+        .map(|(repo_link, commit_hash, package_name, commit, elapsed)| {
+            println!("  {}@{} ({:.2?})", repo_link, &commit[..7], elapsed);
+            (repo_link, commit_hash, package_name, commit)
+        })
+        .collect())
+}
@@ -5,6 +5,8 @@ use std::fs::File;
 use std::io::Write;
 
 use crate::cmd::{Execute, Synth};
+use crate::design::{parse_file, DesignIndex};
+use crate::targets::{select_sources, with_file};
 
 impl Execute for Synth {
     async fn execute(&self) -> Result<()> {
@@ -13,7 +15,8 @@ impl Execute for Synth {
             self.riscv,
             self.core_path.as_ref(),
             &self.board,
-            self.gen_yosys_script
+            self.gen_yosys_script,
+            self.target.as_deref()
         )
     }
 }
@@ -23,10 +26,30 @@ fn synthesize_design(
     riscv: bool,
     core_path: Option<&String>,
     board: &Option<String>,
-    gen_yosys_script: bool
+    gen_yosys_script: bool,
+    target: Option<&str>
 ) -> Result<()> {
     let top_module_path = PathBuf::from(top_module_path);
-    let (input_file, module_name, parent_dir, _) = extract_path_info(&top_module_path);
+    let (mut input_file, mut module_name, parent_dir, _) = extract_path_info(&top_module_path);
+    // The top module is the one the file is named after, or the first one it declares.
+    let declared = parse_file(&top_module_path)?;
+    if let Some(top) = declared.iter().find(|m| m.name == module_name).or(declared.first()) {
+        module_name = top.name.clone();
+    }
+    if let Some(target) = target {
+        // read_verilog takes the defines, include dirs and every file of the target at once.
+        let sources = with_file(select_sources(target)?, &top_module_path);
+        input_file = sources.include_and_define_args().into_iter()
+            .chain(sources.files.iter().map(|file| file.display().to_string()))
+            .collect::<Vec<_>>()
+            .join(" ");
+    } else {
+        // Read the vendored and local files of every module below the top as well.
+        let files = DesignIndex::load().dependency_files(&module_name);
+        if files.len() > 1 {
+            input_file = files.iter().map(|file| file.display().to_string()).collect::<Vec<_>>().join(" ");
+        }
+    }
     
     let script_content = match board {
         Some(board) if board.to_lowercase() == "xilinx" => {
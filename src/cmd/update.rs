@@ -34,7 +34,7 @@ fn update_module(module_path: &str, commit: Option<&str>) -> Result<()> {
             .ok_or_else(|| anyhow::anyhow!("Invalid choice"))?
     };
 
-    let head_commit_hash = get_head_commit_hash(&chosen_repo).unwrap();
+    let head_commit_hash = get_head_commit_hash(&chosen_repo, None).unwrap();
     let commit_hash = commit.unwrap_or(&head_commit_hash);
 
     println!("Updating module '{}' to commit '{}'", module_path, commit_hash);
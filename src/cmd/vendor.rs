@@ -0,0 +1,11 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::cmd::{Execute, Vendor};
+use crate::vendor::create_archive;
+
+impl Execute for Vendor {
+    async fn execute(&self) -> Result<()> {
+        create_archive(Path::new(&self.archive)).await
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use directories::ProjectDirs;
 use rand::RngCore;
 use reqwest::Client;
@@ -46,6 +46,14 @@ pub async fn send_event(command: String) -> Result<()> {
     Ok(())
 }
 
+/// The per-user cache directory that clones, indexes and generated sources are kept in.
+pub fn cache_dir() -> Result<PathBuf> {
+    Ok(ProjectDirs::from("com", "Instachip", "vpm")
+        .context("Failed to locate the cache directory")?
+        .cache_dir()
+        .to_path_buf())
+}
+
 pub fn get_config_path() -> Option<PathBuf> {
     ProjectDirs::from("com", "Instachip", "vpm")
         .map(|proj_dirs| proj_dirs.config_dir().to_path_buf())
@@ -159,6 +167,144 @@ pub fn set_version(version: &str) -> Result<()> {
     Ok(())
 }   
 
+/// Looks up an access token for `host`, first from the environment and then from the
+/// `[credentials."<host>"]` table of config.toml. Returns `(username, token)`.
+pub fn get_credentials(host: &str) -> Option<(String, String)> {
+    let default_username = if host.contains("github") { "x-access-token" } else { "oauth2" };
+    let host_var = format!("VPM_TOKEN_{}", host.to_uppercase().replace(|c: char| !c.is_ascii_alphanumeric(), "_"));
+    let well_known_vars: &[&str] = match host {
+        "github.com" => &["GITHUB_TOKEN", "GH_TOKEN"],
+        "gitlab.com" => &["GITLAB_TOKEN"],
+        _ => &[],
+    };
+    if let Some(token) = std::iter::once(host_var.as_str())
+        .chain(well_known_vars.iter().copied())
+        .find_map(|var| std::env::var(var).ok().filter(|t| !t.is_empty()))
+    {
+        return Some((default_username.to_string(), token));
+    }
+
+    let config_path = get_config_path()?;
+    let config = fs::read_to_string(config_path).ok()?;
+    let config_doc = config.parse::<DocumentMut>().ok()?;
+    let entry = config_doc.get("credentials")?.get(host)?;
+    let token = entry.get("token")?.as_str()?.to_string();
+    let username = entry.get("username").and_then(|u| u.as_str()).unwrap_or(default_username).to_string();
+    Some((username, token))
+}
+
+pub fn set_credentials(host: &str, token: &str) -> Result<()> {
+    let config_path = get_config_path().unwrap();
+    if !config_path.exists() {
+        create_config()?;
+    }
+    let config = fs::read_to_string(config_path.clone())?;
+    let mut config_doc = config.parse::<DocumentMut>().expect("Failed to parse config.toml");
+    if config_doc.get("credentials").is_none() {
+        let mut credentials = Table::new();
+        credentials.set_implicit(true);
+        config_doc.insert("credentials", Item::Table(credentials));
+    }
+    config_doc["credentials"][host]["token"] = Item::Value(Value::from(token));
+    fs::write(config_path, config_doc.to_string()).expect("Failed to write config.toml");
+    Ok(())
+}
+
+/// Returns the `(prefix, replacement)` URL rewrite rules declared in config.toml as
+/// `[url."<replacement>"] insteadOf = "<prefix>"`, longest prefix first.
+pub fn get_url_rewrites() -> Vec<(String, String)> {
+    let mut rewrites = Vec::new();
+    let config_doc = get_config_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|config| config.parse::<DocumentMut>().ok());
+    if let Some(urls) = config_doc.as_ref().and_then(|doc| doc.get("url")).and_then(|u| u.as_table_like()) {
+        for (replacement, rule) in urls.iter() {
+            let prefixes: Vec<String> = match rule.get("insteadOf") {
+                Some(item) if item.is_str() => vec![item.as_str().unwrap().to_string()],
+                Some(item) => item.as_array()
+                    .map(|a| a.iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect())
+                    .unwrap_or_default(),
+                None => Vec::new(),
+            };
+            rewrites.extend(prefixes.into_iter().map(|prefix| (prefix, replacement.to_string())));
+        }
+    }
+    rewrites.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+    rewrites
+}
+
+pub fn set_url_rewrite(prefix: &str, replacement: &str) -> Result<()> {
+    let config_path = get_config_path().unwrap();
+    if !config_path.exists() {
+        create_config()?;
+    }
+    let config = fs::read_to_string(config_path.clone())?;
+    let mut config_doc = config.parse::<DocumentMut>().expect("Failed to parse config.toml");
+    if config_doc.get("url").is_none() {
+        let mut urls = Table::new();
+        urls.set_implicit(true);
+        config_doc.insert("url", Item::Table(urls));
+    }
+    config_doc["url"][replacement]["insteadOf"] = Item::Value(Value::from(prefix));
+    fs::write(config_path, config_doc.to_string()).expect("Failed to write config.toml");
+    Ok(())
+}
+
+/// Location of the package registry index: `VPM_REGISTRY`, then `[registry] index` in
+/// config.toml, then a local index in the user data directory.
+pub fn get_registry_index() -> String {
+    if let Ok(index) = std::env::var("VPM_REGISTRY") {
+        return index;
+    }
+    let configured = get_config_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|config| config.parse::<DocumentMut>().ok())
+        .and_then(|doc| doc.get("registry")?.get("index")?.as_str().map(|s| s.to_string()));
+    configured.unwrap_or_else(|| {
+        ProjectDirs::from("com", "Instachip", "vpm")
+            .map(|proj_dirs| proj_dirs.data_dir().join("registry").to_string_lossy().into_owned())
+            .unwrap_or_else(|| "registry".to_string())
+    })
+}
+
+/// Whether sparse clones fetch file contents on demand (`VPM_PARTIAL_CLONE`, then
+/// `[options] partial_clone` in config.toml). Off by default, since it needs the git
+/// command line on PATH.
+pub fn get_partial_clone() -> bool {
+    if let Ok(value) = std::env::var("VPM_PARTIAL_CLONE") {
+        return matches!(value.as_str(), "1" | "true");
+    }
+    get_config_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|config| config.parse::<DocumentMut>().ok())
+        .and_then(|doc| doc.get("options")?.get("partial_clone")?.as_bool())
+        .unwrap_or(false)
+}
+
+pub fn set_partial_clone(value: bool) -> Result<()> {
+    let config_path = get_config_path().unwrap();
+    if !config_path.exists() {
+        create_config()?;
+    }
+    let config = fs::read_to_string(config_path.clone())?;
+    let mut config_doc = config.parse::<DocumentMut>().expect("Failed to parse config.toml");
+    config_doc["options"]["partial_clone"] = Item::Value(Value::from(value));
+    fs::write(config_path, config_doc.to_string()).expect("Failed to write config.toml");
+    Ok(())
+}
+
+pub fn set_registry_index(index: &str) -> Result<()> {
+    let config_path = get_config_path().unwrap();
+    if !config_path.exists() {
+        create_config()?;
+    }
+    let config = fs::read_to_string(config_path.clone())?;
+    let mut config_doc = config.parse::<DocumentMut>().expect("Failed to parse config.toml");
+    config_doc["registry"]["index"] = Item::Value(Value::from(index));
+    fs::write(config_path, config_doc.to_string()).expect("Failed to write config.toml");
+    Ok(())
+}
+
 pub fn decrypt_docs_count() -> Result<u8> {
     let config_path = get_config_path().ok_or(anyhow::anyhow!("Failed to get config path"))?;
     if !config_path.exists() {
@@ -0,0 +1,633 @@
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tree_sitter::{Node, Parser, Query, QueryCursor};
+
+use crate::preprocess::{preprocess, DefineSet};
+use crate::registry::sha256_checksum;
+
+/// Bumped whenever the parser or `ModuleInterface` changes, so stale cache entries are
+/// not read back.
+const CACHE_VERSION: &str = "v3";
+
+/// The interface of a single module declaration.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ModuleInterface {
+    pub name: String,
+    /// `(name, default)` for each parameter, in declaration order.
+    pub parameters: Vec<(String, Option<String>)>,
+    /// `(direction, width, name)` for each port, in declaration order.
+    pub ports: Vec<(String, Option<String>, String)>,
+    /// Each parameter as declared, such as `parameter int unsigned DEPTH = 16`, in the order
+    /// of `parameters`.
+    #[serde(default)]
+    pub parameter_declarations: Vec<String>,
+    /// Each port as an ANSI declaration with its data type and dimensions as written, such
+    /// as `input logic signed [7:0] data [4]` or `bus_if.master bus`, in the order of `ports`.
+    #[serde(default)]
+    pub port_declarations: Vec<String>,
+    /// Names of the modules instantiated in the body, in order of first use.
+    pub submodules: Vec<String>,
+    /// Every instance in the body, in source order.
+    pub instances: Vec<Instantiation>,
+}
+
+/// A module instance found in a Verilog source.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Instantiation {
+    pub module: String,
+    pub instance: String,
+    /// Parameter overrides as `(name, value)`. Ordered overrides have no name.
+    pub parameters: Vec<(Option<String>, String)>,
+}
+
+/// Finds every module instance below `root_node`, including instances inside generate
+/// blocks and each instance of a comma-separated list or instance array.
+pub fn get_instantiations(root_node: Node, contents: &str) -> Vec<Instantiation> {
+    static QUERY: Lazy<Query> = Lazy::new(|| {
+        Query::new(tree_sitter_verilog::language(), "(module_instantiation) @instantiation")
+            .expect("Failed to create query")
+    });
+    let text = |node: Node| contents[node.byte_range()].trim().to_string();
+
+    let mut instantiations = Vec::new();
+    let mut query_cursor = QueryCursor::new();
+    for match_ in query_cursor.matches(&QUERY, root_node, contents.as_bytes()) {
+        for capture in match_.captures {
+            let node = capture.node;
+            let Some(module) = node.named_child(0).map(text) else { continue };
+
+            let mut parameters = Vec::new();
+            let mut pending = children(node).into_iter().filter(|c| c.kind() == "parameter_value_assignment").collect::<Vec<_>>();
+            while let Some(current) = pending.pop() {
+                match current.kind() {
+                    "named_parameter_assignment" => {
+                        let assignment = text(current);
+                        let (name, value) = assignment.trim_start_matches('.').split_once('(').unwrap_or((&assignment, ""));
+                        let value = value.trim_end().strip_suffix(')').unwrap_or(value);
+                        parameters.push((Some(name.trim().to_string()), value.trim().to_string()));
+                    }
+                    "ordered_parameter_assignment" => parameters.push((None, text(current))),
+                    _ => pending.extend(children(current).into_iter().rev()),
+                }
+            }
+
+            for instance in children(node).into_iter().filter(|c| c.kind() == "hierarchical_instance") {
+                let instance = children(instance).into_iter()
+                    .find(|c| c.kind() == "name_of_instance")
+                    .map(text)
+                    .unwrap_or_default();
+                instantiations.push(Instantiation { module: module.clone(), instance, parameters: parameters.clone() });
+            }
+        }
+    }
+    instantiations
+}
+
+/// Names of the modules instantiated in `contents`, in order of first use. Includes are
+/// looked up in `include_dirs` when `defines` are given.
+pub fn get_submodules(contents: &str, include_dirs: &[PathBuf], defines: Option<&DefineSet>) -> Result<Vec<String>> {
+    let mut submodules = Vec::new();
+    for module in parse_configured(contents, include_dirs, defines)?.modules {
+        for submodule in module.submodules {
+            if !submodules.contains(&submodule) {
+                submodules.push(submodule);
+            }
+        }
+    }
+    Ok(submodules)
+}
+/// Files, packages and interfaces a source needs besides the modules it instantiates.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SourceDependencies {
+    /// Paths named by `` `include `` directives, as written.
+    pub includes: Vec<String>,
+    /// Packages imported with `import pkg::*` or referenced as `pkg::NAME`.
+    pub packages: Vec<String>,
+    /// Interfaces used as port types.
+    pub interfaces: Vec<String>,
+    /// Packages declared in the source itself.
+    pub declared_packages: Vec<String>,
+}
+
+/// Everything cached for one source.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct ParsedSource {
+    modules: Vec<ModuleInterface>,
+    dependencies: SourceDependencies,
+}
+
+/// Modules, ports, parameters and instances of every file in a design, keyed by file.
+#[derive(Debug, Clone, Default)]
+pub struct DesignIndex {
+    pub files: Vec<(PathBuf, Vec<ModuleInterface>)>,
+    /// The includes and packages of each indexed file.
+    pub dependencies: HashMap<PathBuf, SourceDependencies>,
+}
+
+impl DesignIndex {
+    /// Indexes every project and vendored source below the current directory.
+    pub fn load() -> DesignIndex {
+        DesignIndex::from_files(&project_sources())
+    }
+
+    /// Like `load`, but with every source preprocessed with `defines` if given.
+    pub fn load_with_defines(defines: Option<&DefineSet>) -> DesignIndex {
+        DesignIndex::from_files_with_defines(&project_sources(), defines)
+    }
+
+    /// Indexes `paths`, skipping files that cannot be read or parsed.
+    pub fn from_files(paths: &[PathBuf]) -> DesignIndex {
+        DesignIndex::from_files_with_defines(paths, None)
+    }
+
+    /// Like `from_files`, but with every file preprocessed with `defines` if given.
+    pub fn from_files_with_defines(paths: &[PathBuf], defines: Option<&DefineSet>) -> DesignIndex {
+        let mut index = DesignIndex::default();
+        for path in paths {
+            match parse_path(path, defines) {
+                Ok(parsed) => {
+                    index.files.push((path.clone(), parsed.modules));
+                    index.dependencies.insert(path.clone(), parsed.dependencies);
+                }
+                Err(e) => eprintln!("Warning: Skipping '{}': {}", path.display(), e),
+            }
+        }
+        index
+    }
+
+    /// Every declared module with the file that declares it.
+    pub fn modules(&self) -> impl Iterator<Item = (&Path, &ModuleInterface)> {
+        self.files.iter().flat_map(|(path, modules)| modules.iter().map(move |m| (path.as_path(), m)))
+    }
+
+    /// Finds the declaration of the module called `name`.
+    pub fn find_module(&self, name: &str) -> Option<(&Path, &ModuleInterface)> {
+        self.modules().find(|(_, module)| module.name == name)
+    }
+
+    /// Finds the file declaring the package called `name`.
+    pub fn find_package(&self, name: &str) -> Option<&Path> {
+        self.files.iter()
+            .map(|(path, _)| path.as_path())
+            .find(|path| self.dependencies.get(*path).is_some_and(|d| d.declared_packages.iter().any(|p| p == name)))
+    }
+
+    /// Files declaring `top` and every module and package below it, each after the files
+    /// it depends on.
+    pub fn dependency_files(&self, top: &str) -> Vec<PathBuf> {
+        let mut order = Vec::new();
+        self.visit_module(top, &mut HashSet::new(), &mut order);
+        order
+    }
+
+    /// Orders `files` so that each comes after the files it depends on, keeping the given
+    /// order otherwise. Files the index does not know keep their place at the end.
+    pub fn sort_files(&self, files: &[PathBuf]) -> Vec<PathBuf> {
+        let mut visited = HashSet::new();
+        let mut order = Vec::new();
+        for (path, modules) in self.files.iter().filter(|(path, _)| files.contains(path)) {
+            for module in modules {
+                self.visit_module(&module.name, &mut visited, &mut order);
+            }
+            self.visit_packages(path, &mut visited, &mut order);
+            push_unique(&mut order, path.clone());
+        }
+        order.retain(|path| files.contains(path));
+        for file in files {
+            push_unique(&mut order, file.clone());
+        }
+        order
+    }
+
+    /// Headers pulled in with `` `include `` by `files` and by the headers themselves,
+    /// found next to the including file or in `include_dirs`.
+    pub fn include_files(&self, files: &[PathBuf], include_dirs: &[PathBuf]) -> Vec<PathBuf> {
+        let mut headers: Vec<PathBuf> = Vec::new();
+        let mut pending: Vec<(PathBuf, Vec<String>)> = files.iter()
+            .filter_map(|file| self.dependencies.get(file).map(|d| (file.clone(), d.includes.clone())))
+            .collect();
+        while let Some((file, includes)) = pending.pop() {
+            let dir = file.parent().map(Path::to_path_buf).unwrap_or_default();
+            for include in includes {
+                let Some(header) = std::iter::once(&dir).chain(include_dirs).map(|d| d.join(&include)).find(|c| c.is_file()) else { continue };
+                if headers.contains(&header) {
+                    continue;
+                }
+                let nested = fs::read_to_string(&header).map(|contents| find_includes(&contents)).unwrap_or_default();
+                pending.push((header.clone(), nested));
+                headers.push(header);
+            }
+        }
+        headers
+    }
+
+    fn visit_module(&self, module: &str, visited: &mut HashSet<String>, order: &mut Vec<PathBuf>) {
+        if !visited.insert(module.to_string()) {
+            return;
+        }
+        let Some((path, interface)) = self.find_module(module) else { return };
+        for submodule in &interface.submodules {
+            self.visit_module(submodule, visited, order);
+        }
+        self.visit_packages(path, visited, order);
+        push_unique(order, path.to_path_buf());
+    }
+
+    /// Adds the files declaring the packages `path` uses, each after its own packages.
+    fn visit_packages(&self, path: &Path, visited: &mut HashSet<String>, order: &mut Vec<PathBuf>) {
+        let Some(dependencies) = self.dependencies.get(path) else { return };
+        for package in &dependencies.packages {
+            // Packages share the visited set with modules, so they are marked apart.
+            if !visited.insert(format!("package {}", package)) {
+                continue;
+            }
+            let Some(package_path) = self.find_package(package) else { continue };
+            self.visit_packages(package_path, visited, order);
+            push_unique(order, package_path.to_path_buf());
+        }
+    }
+}
+
+/// Lists every Verilog/SystemVerilog source below the current directory, skipping hidden
+/// directories, build output and the stubs vpm generates in `headers/`.
+pub fn project_sources() -> Vec<PathBuf> {
+    let mut sources: Vec<PathBuf> = walkdir::WalkDir::new(".")
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || !(e.file_name().to_string_lossy().starts_with('.') || e.file_name() == "target" || e.file_name() == "headers"))
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .filter(|p| is_source(p))
+        .collect();
+    sources.sort();
+    sources
+}
+
+/// Parses every module declared in the file at `path`, reusing the cached result for
+/// contents that were parsed before.
+pub fn parse_file(path: &Path) -> Result<Vec<ModuleInterface>> {
+    parse_file_with_defines(path, None)
+}
+
+/// Like `parse_file`, but with the file preprocessed with `defines` if given.
+pub fn parse_file_with_defines(path: &Path, defines: Option<&DefineSet>) -> Result<Vec<ModuleInterface>> {
+    Ok(parse_path(path, defines)?.modules)
+}
+
+fn parse_path(path: &Path, defines: Option<&DefineSet>) -> Result<ParsedSource> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read '{}'", path.display()))?;
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    parse_configured(&contents, &[dir], defines)
+}
+
+/// Finds the includes, packages and interfaces `contents` depends on. Includes are looked
+/// up in `include_dirs` when `defines` are given.
+pub fn source_dependencies(contents: &str, include_dirs: &[PathBuf], defines: Option<&DefineSet>) -> Result<SourceDependencies> {
+    Ok(parse_configured(contents, include_dirs, defines)?.dependencies)
+}
+
+/// Parses `contents` as `defines` configure it, or as written (with every conditional
+/// branch counting) without them. Preprocessed text is cached like any other source, so
+/// each configuration gets its own cache entries.
+fn parse_configured(contents: &str, include_dirs: &[PathBuf], defines: Option<&DefineSet>) -> Result<ParsedSource> {
+    let Some(defines) = defines else { return parse_cached(contents) };
+    let preprocessed = preprocess(contents, defines, include_dirs)?;
+    let mut parsed = parse_cached(&preprocessed.text)?;
+    parsed.dependencies.includes = preprocessed.includes;
+    Ok(parsed)
+}
+
+fn parse_cached(contents: &str) -> Result<ParsedSource> {
+    let cache_file = cache_dir().map(|dir| dir.join(format!("{}.json", sha256_checksum(contents.as_bytes()).trim_start_matches("sha256:"))));
+    if let Some(parsed) = cache_file.as_ref().and_then(|file| fs::read_to_string(file).ok()).and_then(|cached| serde_json::from_str(&cached).ok()) {
+        return Ok(parsed);
+    }
+    let parsed = parse_uncached(contents)?;
+    if let Some(file) = cache_file {
+        // The cache only saves work; failing to write it is not an error.
+        let _ = fs::create_dir_all(file.parent().unwrap())
+            .and_then(|_| fs::write(&file, serde_json::to_string(&parsed).unwrap_or_default()));
+    }
+    Ok(parsed)
+}
+
+fn parse_uncached(contents: &str) -> Result<ParsedSource> {
+    let mut parser = Parser::new();
+    parser.set_language(tree_sitter_verilog::language())?;
+    let tree = parser.parse(contents, None).context("Failed to parse file")?;
+
+    let mut modules = Vec::new();
+    let mut declarations = Vec::new();
+    collect_nodes(tree.root_node(), &|kind| kind == "module_declaration", &mut declarations);
+    for declaration in declarations {
+        modules.push(parse_module(declaration, contents));
+    }
+    Ok(ParsedSource { modules, dependencies: parse_dependencies(tree.root_node(), contents) })
+}
+
+fn parse_dependencies(root: Node, contents: &str) -> SourceDependencies {
+    let text = |node: Node| contents[node.byte_range()].trim().to_string();
+    let mut dependencies = SourceDependencies {
+        includes: find_includes(contents),
+        ..Default::default()
+    };
+
+    let mut identifiers = Vec::new();
+    collect_nodes(root, &|kind| kind == "package_identifier", &mut identifiers);
+    for identifier in identifiers {
+        let name = text(identifier);
+        if identifier.parent().is_some_and(|p| p.kind() == "package_declaration") {
+            push_unique(&mut dependencies.declared_packages, name);
+        } else {
+            push_unique(&mut dependencies.packages, name);
+        }
+    }
+    // `pkg::NAME` in a type position parses as a class scope, since the grammar cannot
+    // tell packages and classes apart.
+    let mut scopes = Vec::new();
+    collect_nodes(root, &|kind| kind == "class_scope" || kind == "package_scope", &mut scopes);
+    for scope in scopes {
+        if let Some((name, _)) = text(scope).split_once("::") {
+            push_unique(&mut dependencies.packages, name.trim().to_string());
+        }
+    }
+    let declared = dependencies.declared_packages.clone();
+    dependencies.packages.retain(|name| !declared.contains(name) && name != "$unit" && name != "std");
+
+    let mut headers = Vec::new();
+    collect_nodes(root, &|kind| kind == "interface_port_header", &mut headers);
+    for header in headers {
+        // `bus_if.master` names the interface and one of its modports.
+        let header = text(header);
+        if let Some(name) = header.split(['.', ' ']).next().filter(|name| *name != "interface" && !name.is_empty()) {
+            push_unique(&mut dependencies.interfaces, name.to_string());
+        }
+    }
+    dependencies
+}
+
+/// `` `include `` is a preprocessor directive, so it is found lexically rather than in the
+/// syntax tree. Directives inside comments are ignored.
+fn find_includes(contents: &str) -> Vec<String> {
+    let mut includes = Vec::new();
+    let mut in_block_comment = false;
+    for line in contents.lines() {
+        let mut line = line;
+        if in_block_comment {
+            match line.find("*/") {
+                Some(end) => {
+                    line = &line[end + 2..];
+                    in_block_comment = false;
+                }
+                None => continue,
+            }
+        }
+        let code = line.split("//").next().unwrap_or_default();
+        if let Some(start) = code.find("/*") {
+            in_block_comment = !code[start..].contains("*/");
+        }
+        let Some(rest) = code.trim_start().strip_prefix("`include") else { continue };
+        let rest = rest.trim_start();
+        let (open, close) = match rest.chars().next() {
+            Some('"') => ('"', '"'),
+            Some('<') => ('<', '>'),
+            _ => continue,
+        };
+        if let Some(path) = rest.trim_start_matches(open).split(close).next().filter(|p| !p.is_empty()) {
+            push_unique(&mut includes, path.to_string());
+        }
+    }
+    includes
+}
+
+/// Appends `item` unless `items` already holds it, keeping the first-seen order.
+pub fn push_unique<T: PartialEq>(items: &mut Vec<T>, item: T) {
+    if !items.contains(&item) {
+        items.push(item);
+    }
+}
+
+/// Whether `path` is a Verilog or SystemVerilog source file, as opposed to a header.
+pub fn is_source(path: &Path) -> bool {
+    matches!(path.extension().and_then(|e| e.to_str()), Some("v" | "sv"))
+}
+
+/// Whether `path` is a Verilog or SystemVerilog source or header file.
+pub fn is_rtl(path: &Path) -> bool {
+    matches!(path.extension().and_then(|e| e.to_str()), Some("v" | "sv" | "vh" | "svh" | "svi"))
+}
+
+fn parse_module(declaration: Node, contents: &str) -> ModuleInterface {
+    let text = |node: Node| contents[node.byte_range()].trim().to_string();
+    let mut interface = ModuleInterface::default();
+
+    let header = children(declaration).into_iter().find(|c| c.kind() == "module_header");
+    if let Some(name) = header.and_then(|h| children(h).into_iter().find(|c| c.kind() == "simple_identifier")) {
+        interface.name = text(name);
+    }
+
+    let mut assignments = Vec::new();
+    collect_nodes(declaration, &|kind| kind == "param_assignment", &mut assignments);
+    for assignment in assignments {
+        // Skip localparams; they are not part of the interface.
+        let is_local = ancestors(assignment, declaration).iter().any(|a| a.kind() == "local_parameter_declaration");
+        let Some(name) = children(assignment).into_iter().find(|c| c.kind() == "parameter_identifier") else { continue };
+        if !is_local {
+            let default = text(assignment).split_once('=').map(|(_, value)| value.trim().to_string());
+            interface.parameters.push((text(name), default));
+            // Assignments in one declaration share its keyword and type.
+            let header = ancestors(assignment, declaration).into_iter()
+                .find(|a| a.kind() == "parameter_declaration" || a.kind() == "parameter_port_declaration")
+                .and_then(|d| find_node(d, "param_assignment").map(|first| squeeze(&contents[d.start_byte()..first.start_byte()])))
+                .filter(|header| !header.is_empty())
+                .unwrap_or_else(|| "parameter".to_string());
+            interface.parameter_declarations.push(format!("{} {}", header, squeeze(&text(assignment))));
+        }
+    }
+
+    let mut declarations = Vec::new();
+    collect_nodes(declaration, &|kind| {
+        kind == "ansi_port_declaration" || kind == "input_declaration" || kind == "output_declaration" || kind == "inout_declaration"
+    }, &mut declarations);
+    // ANSI ports without a direction inherit the direction of the previous port, and ports
+    // without any header inherit the whole header.
+    let mut direction = "inout".to_string();
+    let mut port_header = String::new();
+    for port in declarations {
+        let kind = port.kind();
+        if let Some(explicit) = kind.strip_suffix("_declaration").filter(|d| *d != "ansi_port") {
+            direction = explicit.to_string();
+        } else if let Some(node) = find_node(port, "port_direction") {
+            direction = text(node);
+        }
+        let width = find_node(port, "packed_dimension").map(text);
+        let mut identifiers = Vec::new();
+        collect_nodes(port, &|kind| kind == "port_identifier", &mut identifiers);
+        for (i, identifier) in identifiers.iter().enumerate() {
+            if i == 0 {
+                let header = squeeze(&contents[port.start_byte()..identifier.start_byte()]);
+                if !header.is_empty() {
+                    port_header = header;
+                }
+            }
+            // Unpacked dimensions follow the name, up to the next port or a default value.
+            let end = identifiers.get(i + 1).map_or(port.end_byte(), |next| next.start_byte());
+            let rest = &contents[identifier.end_byte()..end];
+            let dimensions = squeeze(rest.split([',', ';', '=', ')']).next().unwrap_or_default());
+            let name = text(*identifier);
+            interface.port_declarations.push([port_header.as_str(), name.as_str(), dimensions.as_str()].into_iter()
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join(" "));
+            interface.ports.push((direction.clone(), width.clone(), name));
+        }
+    }
+
+    interface.instances = get_instantiations(declaration, contents);
+    for instantiation in &interface.instances {
+        let module = &instantiation.module;
+        if !interface.submodules.contains(module) && *module != interface.name && !module.starts_with('`') {
+            interface.submodules.push(module.clone());
+        }
+    }
+    interface
+}
+
+/// Collapses runs of whitespace, so declarations split over several lines read as one.
+fn squeeze(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn children(node: Node) -> Vec<Node> {
+    let mut cursor = node.walk();
+    node.children(&mut cursor).collect()
+}
+
+fn ancestors<'a>(node: Node<'a>, root: Node<'a>) -> Vec<Node<'a>> {
+    let mut ancestors = Vec::new();
+    let mut current = node.parent();
+    while let Some(parent) = current {
+        if parent.id() == root.id() {
+            break;
+        }
+        ancestors.push(parent);
+        current = parent.parent();
+    }
+    ancestors
+}
+
+fn find_node<'a>(node: Node<'a>, kind: &str) -> Option<Node<'a>> {
+    let mut found = Vec::new();
+    collect_nodes(node, &|k| k == kind, &mut found);
+    found.into_iter().next()
+}
+
+/// Collects the outermost descendants of `node` whose kind matches, in source order.
+fn collect_nodes<'a>(node: Node<'a>, matches: &dyn Fn(&str) -> bool, found: &mut Vec<Node<'a>>) {
+    for child in children(node) {
+        if matches(child.kind()) {
+            found.push(child);
+        } else {
+            collect_nodes(child, matches, found);
+        }
+    }
+}
+
+fn cache_dir() -> Option<PathBuf> {
+    crate::config_man::cache_dir().ok().map(|dir| dir.join("design-index").join(CACHE_VERSION))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preprocess::DefineSet;
+
+    fn fixture(name: &str) -> String {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/design").join(name);
+        fs::read_to_string(&path).unwrap_or_else(|e| panic!("Failed to read '{}': {}", path.display(), e))
+    }
+
+    fn parse_fixture(name: &str) -> ParsedSource {
+        parse_uncached(&fixture(name)).unwrap()
+    }
+
+    /// `(module, instance)` for every instance in the first module of `parsed`.
+    fn instances(parsed: &ParsedSource) -> Vec<(String, String)> {
+        parsed.modules[0].instances.iter().map(|i| (i.module.clone(), i.instance.clone())).collect()
+    }
+
+    fn pairs(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+        expected.iter().map(|(module, instance)| (module.to_string(), instance.to_string())).collect()
+    }
+
+    #[test]
+    fn finds_instances_in_generate_blocks() {
+        let parsed = parse_fixture("generate_blocks.sv");
+        assert_eq!(instances(&parsed), pairs(&[
+            ("lane", "u_lane"),
+            ("extra", "u_extra"),
+            ("plain", "u_plain"),
+            ("bare", "u_bare"),
+        ]));
+        assert_eq!(parsed.modules[0].submodules, ["lane", "extra", "plain", "bare"]);
+    }
+
+    #[test]
+    fn finds_instance_arrays_and_lists() {
+        let parsed = parse_fixture("instance_arrays.v");
+        assert_eq!(instances(&parsed), pairs(&[
+            ("buffer", "u_buf[3:0]"),
+            ("inverter", "u_inv0"),
+            ("inverter", "u_inv1"),
+        ]));
+        assert_eq!(parsed.modules[0].submodules, ["buffer", "inverter"]);
+    }
+
+    #[test]
+    fn reads_multi_line_parameter_overrides() {
+        let parsed = parse_fixture("parameter_overrides.v");
+        let instances = &parsed.modules[0].instances;
+        assert_eq!(instances.len(), 2);
+        assert_eq!((instances[0].module.as_str(), instances[0].instance.as_str()), ("fifo", "u_fifo"));
+        assert_eq!(instances[0].parameters, [
+            (Some("WIDTH".to_string()), "8".to_string()),
+            (Some("DEPTH".to_string()), "16".to_string()),
+        ]);
+        assert_eq!((instances[1].module.as_str(), instances[1].instance.as_str()), ("counter", "u_counter"));
+        assert_eq!(instances[1].parameters, [(None, "4".to_string()), (None, "10".to_string())]);
+    }
+
+    #[test]
+    fn skips_macro_named_modules_until_preprocessed() {
+        let contents = fixture("macro_modules.sv");
+        let parsed = parse_uncached(&contents).unwrap();
+        assert!(parsed.modules[0].submodules.iter().all(|m| !m.starts_with('`')));
+        assert!(parsed.modules[0].submodules.contains(&"counter".to_string()));
+
+        let preprocessed = preprocess(&contents, &DefineSet::from_defines(&[]), &[]).unwrap();
+        let parsed = parse_uncached(&preprocessed.text).unwrap();
+        assert_eq!(instances(&parsed), pairs(&[("sync_fifo", "u_fifo"), ("counter", "u_counter")]));
+    }
+
+    #[test]
+    fn net_declarations_are_not_instances() {
+        let parsed = parse_fixture("net_declarations.v");
+        assert_eq!(parsed.modules[0].name, "nets");
+        assert!(parsed.modules[0].instances.is_empty());
+        assert!(parsed.modules[0].submodules.is_empty());
+    }
+
+    #[test]
+    fn interface_ports_are_dependencies_not_instances() {
+        let parsed = parse_fixture("interface_ports.sv");
+        assert_eq!(instances(&parsed), pairs(&[("arbiter", "u_arbiter")]));
+        assert_eq!(parsed.dependencies.interfaces, ["bus_if"]);
+        let ports: Vec<&str> = parsed.modules[0].ports.iter().map(|(_, _, name)| name.as_str()).collect();
+        assert_eq!(ports, ["clk", "upstream", "downstream"]);
+    }
+}
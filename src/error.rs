@@ -12,3 +12,30 @@ impl Display for SilentExit {
         Ok(())
     }
 }
+
+impl std::error::Error for SilentExit {}
+
+/// Errors raised by the git backend
+#[derive(Debug)]
+pub enum GitError {
+    Auth { url: String, message: String },
+    UnknownRef { url: String, reference: String },
+    Network { url: String, message: String },
+    /// The git command line, used for partial clones, exited with an error.
+    Command { command: String, stderr: String },
+    Other(git2::Error),
+}
+
+impl Display for GitError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            GitError::Auth { url, message } => write!(f, "Authentication failed for '{}': {}", url, message),
+            GitError::UnknownRef { url, reference } => write!(f, "Reference '{}' not found in '{}'", reference, url),
+            GitError::Network { url, message } => write!(f, "Network error while fetching '{}': {}", url, message),
+            GitError::Command { command, stderr } => write!(f, "'git {}' failed: {}", command, stderr.trim()),
+            GitError::Other(e) => write!(f, "Git error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for GitError {}
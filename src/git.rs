@@ -0,0 +1,430 @@
+use anyhow::{Context, Result};
+use git2::{
+    AutotagOption, Cred, CredentialType, Direction, ErrorClass, ErrorCode, FetchOptions, ObjectType,
+    Oid, Remote, RemoteCallbacks, Repository, TreeWalkMode, TreeWalkResult,
+};
+use git2::build::CheckoutBuilder;
+use indicatif::ProgressBar;
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::config_man::{get_credentials, get_partial_clone, get_url_rewrites};
+use crate::error::GitError;
+
+const FULL_HISTORY_REFSPECS: [&str; 2] = ["+refs/heads/*:refs/remotes/origin/*", "+refs/tags/*:refs/tags/*"];
+
+/// Returns the full hash of the commit the remote's HEAD points to.
+pub fn get_remote_head(url: &str) -> Result<String> {
+    let mut remote = Remote::create_detached(rewrite_url(url)).map_err(|e| classify_error(e, url, None))?;
+    let connection = remote
+        .connect_auth(Direction::Fetch, Some(remote_callbacks(None)), None)
+        .map_err(|e| classify_error(e, url, None))?;
+    let heads = connection.list().map_err(|e| classify_error(e, url, None))?;
+    heads.iter()
+        .find(|head| head.name() == "HEAD")
+        .map(|head| head.oid().to_string())
+        .ok_or_else(|| GitError::UnknownRef { url: url.to_string(), reference: "HEAD".to_string() }.into())
+}
+
+/// Clones `url` into `repo_path` at `commit_hash` (or the default branch HEAD) and
+/// returns the full hash of the commit that was checked out.
+///
+/// A sparse clone fetches the commit without checking anything out; files are only
+/// written to disk once they are requested with `materialize_files`. With partial clones
+/// enabled, their contents are only downloaded then as well.
+pub fn clone_repo(url: &str, repo_path: &Path, commit_hash: Option<&str>, sparse: bool, progress: Option<&ProgressBar>) -> Result<String> {
+    if repo_path.exists() {
+        fs::remove_dir_all(repo_path)?;
+    }
+    let repo = Repository::init(repo_path)
+        .with_context(|| format!("Failed to initialise repository at '{}'", repo_path.display()))?;
+    let mut remote = repo.remote("origin", &rewrite_url(url)).map_err(|e| classify_error(e, url, None))?;
+
+    let target = match commit_hash {
+        Some(hash) => hash.to_string(),
+        None => get_remote_head(url)?,
+    };
+
+    // Servers only serve unadvertised commits when asked for by full hash, so
+    // anything else (short hashes, tags, or a refusing server) falls back to full history.
+    let full_hash = Oid::from_str(&target).is_ok() && target.len() == 40;
+    let fetched_shallow = if full_hash && sparse && partial_fetch(repo_path, &target)? {
+        true
+    } else if full_hash {
+        match remote.fetch(&[target.as_str()], Some(&mut fetch_options(progress, 1)), None) {
+            Ok(()) => true,
+            Err(e) => match classify_error(e, url, Some(&target)) {
+                GitError::UnknownRef { .. } => false,
+                error => return Err(error.into()),
+            },
+        }
+    } else {
+        false
+    };
+    if !fetched_shallow {
+        remote.fetch(&FULL_HISTORY_REFSPECS, Some(&mut fetch_options(progress, 0)), None)
+            .map_err(|e| classify_error(e, url, None))?;
+    }
+
+    let commit = repo.revparse_single(&target)
+        .and_then(|object| object.peel_to_commit())
+        .map_err(|_| GitError::UnknownRef { url: url.to_string(), reference: target.clone() })?;
+    repo.set_head_detached(commit.id())?;
+    if !sparse {
+        repo.checkout_head(Some(CheckoutBuilder::new().force()))
+            .with_context(|| format!("Failed to checkout commit hash: '{}'", target))?;
+    }
+
+    let checked_out = commit.id().to_string();
+    if Oid::from_str(&target).is_ok() {
+        anyhow::ensure!(
+            checked_out.starts_with(&target.to_lowercase()),
+            "Checked out commit '{}' does not match requested commit '{}'", checked_out, target
+        );
+    }
+    Ok(checked_out)
+}
+
+/// Moves the sparse clone at `repo_path` to `commit`, fetching only the objects it is
+/// missing, and returns the commit the clone was at before. Clones from scratch (and
+/// returns `None`) if there is no usable repository there yet.
+pub fn update_repo(url: &str, repo_path: &Path, commit: &str) -> Result<Option<String>> {
+    let Ok(repo) = Repository::open(repo_path) else {
+        clone_repo(url, repo_path, Some(commit), true, None)?;
+        return Ok(None);
+    };
+    let previous = repo.head().ok().and_then(|head| head.target()).map(|oid| oid.to_string());
+    let fetched = partial_fetch(repo_path, commit)? || repo.find_remote("origin")
+        .and_then(|mut remote| remote.fetch(&[commit], Some(&mut fetch_options(None, 1)), None))
+        .is_ok();
+    let target = repo.revparse_single(commit).and_then(|object| object.peel_to_commit());
+    let (true, Ok(target)) = (fetched, target) else {
+        drop(repo);
+        clone_repo(url, repo_path, Some(commit), true, None)?;
+        return Ok(None);
+    };
+    repo.set_head_detached(target.id())?;
+
+    // Files materialised from the previous commit would otherwise be taken as current.
+    for entry in fs::read_dir(repo_path)?.filter_map(|e| e.ok()).filter(|e| e.file_name() != ".git") {
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(previous)
+}
+
+/// Lists the paths whose contents differ between commits `from` and `to` of the
+/// repository at `repo_path`, including added and deleted files.
+pub fn changed_paths(repo_path: &Path, from: &str, to: &str) -> Result<HashSet<String>> {
+    let repo = Repository::open(repo_path)
+        .with_context(|| format!("Failed to open repository at '{}'", repo_path.display()))?;
+    let old_tree = repo.revparse_single(from)?.peel_to_tree()?;
+    let new_tree = repo.revparse_single(to)?.peel_to_tree()?;
+    let diff = repo.diff_tree_to_tree(Some(&old_tree), Some(&new_tree), None)?;
+    Ok(diff.deltas()
+        .flat_map(|delta| [delta.old_file().path(), delta.new_file().path()])
+        .flatten()
+        .map(|path| path.to_string_lossy().replace('\\', "/"))
+        .collect())
+}
+
+/// Fetches `commit` without any file contents, which git downloads on demand when
+/// `materialize_files` checks them out. Returns false unless partial clones are enabled
+/// with `vpm config --partial-clone true`. libgit2 cannot make partial clones, so this
+/// needs the git command line.
+fn partial_fetch(repo_path: &Path, commit: &str) -> Result<bool> {
+    if !get_partial_clone() {
+        return Ok(false);
+    }
+    which::which("git").context("Partial clones need the git command line on PATH")?;
+    // Marking the remote as a promisor lets later git commands fetch the missing blobs.
+    let mut config = Repository::open(repo_path)?.config()?;
+    config.set_bool("remote.origin.promisor", true)?;
+    config.set_str("remote.origin.partialclonefilter", "blob:none")?;
+    run_git(repo_path, &["fetch", "--quiet", "--depth=1", "--filter=blob:none", "origin", commit])?;
+    Ok(true)
+}
+
+/// Runs the git command line in `repo_path`, failing with git's own error output.
+fn run_git(repo_path: &Path, args: &[&str]) -> Result<()> {
+    let output = Command::new("git")
+        .arg("-C").arg(repo_path)
+        .args(args)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output()
+        .with_context(|| format!("Failed to run git in '{}'", repo_path.display()))?;
+    if !output.status.success() {
+        return Err(GitError::Command {
+            command: args.join(" "),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        }.into());
+    }
+    Ok(())
+}
+
+/// Lists every file path (relative to the repository root) of the checked out commit,
+/// whether or not it has been materialised on disk.
+pub fn list_repo_files(repo_path: &Path) -> Result<Vec<String>> {
+    Ok(list_repo_blobs(repo_path)?.into_iter().map(|(path, _)| path).collect())
+}
+
+/// Like `list_repo_files`, but also returns the blob hash of each file so callers can
+/// tell which files changed between two commits without reading them.
+pub fn list_repo_blobs(repo_path: &Path) -> Result<Vec<(String, String)>> {
+    let repo = Repository::open(repo_path)
+        .with_context(|| format!("Failed to open repository at '{}'", repo_path.display()))?;
+    let tree = repo.head()?.peel_to_tree()?;
+    let mut files = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+        if entry.kind() == Some(ObjectType::Blob) {
+            files.push((format!("{}{}", root, entry.name().unwrap_or_default()), entry.id().to_string()));
+        }
+        TreeWalkResult::Ok
+    })?;
+    Ok(files)
+}
+
+/// Fetches the full history of `url` into `repo_path`, reusing objects from earlier fetches.
+pub fn fetch_history(url: &str, repo_path: &Path, progress: Option<&ProgressBar>) -> Result<()> {
+    let repo = match Repository::open(repo_path) {
+        Ok(repo) => repo,
+        Err(_) => {
+            if repo_path.exists() {
+                fs::remove_dir_all(repo_path)?;
+            }
+            let repo = Repository::init_bare(repo_path)
+                .with_context(|| format!("Failed to initialise repository at '{}'", repo_path.display()))?;
+            repo.remote("origin", &rewrite_url(url)).map_err(|e| classify_error(e, url, None))?;
+            repo
+        }
+    };
+    let mut remote = repo.find_remote("origin")?;
+    remote.fetch(&FULL_HISTORY_REFSPECS, Some(&mut fetch_options(progress, 0)), None)
+        .map_err(|e| classify_error(e, url, None))?;
+    Ok(())
+}
+
+/// Computes the git blob hash of `contents`, the same hash `list_repo_blobs` reports.
+pub fn blob_hash(contents: &[u8]) -> Result<String> {
+    Ok(Oid::hash_object(ObjectType::Blob, contents)?.to_string())
+}
+
+/// A commit hash and the `(blob, path)` pairs found in its tree.
+pub type CommitMatches = (String, Vec<(String, String)>);
+
+/// Finds which of `blobs` each commit of the repository at `repo_path` contains. Returns
+/// `(commit, [(blob, path)])` for every commit containing at least one of them, newest first.
+pub fn find_blobs_in_history(repo_path: &Path, blobs: &HashSet<String>) -> Result<Vec<CommitMatches>> {
+    fn tree_matches(repo: &Repository, tree: &git2::Tree, blobs: &HashSet<String>, memo: &mut HashMap<Oid, Vec<(String, String)>>) -> Result<Vec<(String, String)>> {
+        if let Some(found) = memo.get(&tree.id()) {
+            return Ok(found.clone());
+        }
+        let mut found = Vec::new();
+        for entry in tree.iter() {
+            let name = entry.name().unwrap_or_default();
+            match entry.kind() {
+                Some(ObjectType::Blob) if blobs.contains(&entry.id().to_string()) => found.push((entry.id().to_string(), name.to_string())),
+                Some(ObjectType::Tree) => {
+                    let subtree = repo.find_tree(entry.id())?;
+                    for (blob, path) in tree_matches(repo, &subtree, blobs, memo)? {
+                        found.push((blob, format!("{}/{}", name, path)));
+                    }
+                }
+                _ => {}
+            }
+        }
+        memo.insert(tree.id(), found.clone());
+        Ok(found)
+    }
+
+    let repo = Repository::open(repo_path)
+        .with_context(|| format!("Failed to open repository at '{}'", repo_path.display()))?;
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(git2::Sort::TIME)?;
+    if repo.head().is_ok() {
+        revwalk.push_head()?;
+    }
+    revwalk.push_glob("refs/remotes/*")?;
+    revwalk.push_glob("refs/tags/*")?;
+
+    // Trees are shared between commits, so each one is only searched once.
+    let mut memo = HashMap::new();
+    let mut commits = Vec::new();
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        let found = tree_matches(&repo, &commit.tree()?, blobs, &mut memo)?;
+        if !found.is_empty() {
+            commits.push((commit.id().to_string(), found));
+        }
+    }
+    Ok(commits)
+}
+
+/// Writes the given files of a sparse clone to disk from the fetched commit. Files that
+/// are already on disk are left untouched.
+pub fn materialize_files(repo_path: &Path, files: &[PathBuf]) -> Result<()> {
+    let missing: Vec<&Path> = files.iter()
+        .filter(|file| !file.exists())
+        .filter_map(|file| file.strip_prefix(repo_path).ok())
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    // Modules from the same checkout may be vendored in parallel; git cannot check out
+    // into one repository twice at once.
+    static CHECKOUTS: Lazy<Mutex<HashMap<PathBuf, Arc<Mutex<()>>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+    let checkout = CHECKOUTS.lock().unwrap_or_else(|e| e.into_inner()).entry(repo_path.to_path_buf()).or_default().clone();
+    let _guard = checkout.lock().unwrap_or_else(|e| e.into_inner());
+
+    let start = Instant::now();
+    let repo = Repository::open(repo_path)
+        .with_context(|| format!("Failed to open repository at '{}'", repo_path.display()))?;
+    let tree = repo.head()?.peel_to_tree()?;
+
+    // Blobs left out of a partial clone are fetched by git itself, all in one request.
+    let promised: Vec<&Path> = missing.iter()
+        .filter(|file| tree.get_path(file).is_ok_and(|entry| repo.find_blob(entry.id()).is_err()))
+        .copied()
+        .collect();
+    if !promised.is_empty() {
+        let paths: Vec<String> = promised.iter().map(|file| file.to_string_lossy().into_owned()).collect();
+        let mut args = vec!["checkout", "HEAD", "--"];
+        args.extend(paths.iter().map(String::as_str));
+        run_git(repo_path, &args)?;
+    }
+
+    for file in missing.iter().filter(|file| !repo_path.join(file).exists()) {
+        let blob = tree.get_path(file)
+            .and_then(|entry| entry.to_object(&repo))
+            .and_then(|object| object.peel_to_blob())
+            .with_context(|| format!("File '{}' not found in repository", file.display()))?;
+        let destination = repo_path.join(file);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&destination, blob.content())?;
+    }
+    println!("Fetched {} file(s) in {:.2?}", missing.len(), start.elapsed());
+    Ok(())
+}
+
+/// Turns `path` into a repository with a single commit holding all of its files, so
+/// generated sources can go through the same code paths as fetched ones. Returns the
+/// hash of the commit.
+pub fn commit_directory(path: &Path, message: &str) -> Result<String> {
+    let repo = Repository::init(path)
+        .with_context(|| format!("Failed to initialise repository at '{}'", path.display()))?;
+    let mut index = repo.index()?;
+    index.add_all(["*"], git2::IndexAddOption::DEFAULT, None)?;
+    index.write()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let signature = git2::Signature::now("vpm", "vpm@localhost")?;
+    let commit = repo.commit(None, &signature, &signature, message, &tree, &[])?;
+    repo.set_head_detached(commit)?;
+    Ok(commit.to_string())
+}
+
+/// Applies the `insteadOf` rewrite rules from the user config to `url`.
+pub fn rewrite_url(url: &str) -> String {
+    get_url_rewrites().into_iter()
+        .find(|(prefix, _)| url.starts_with(prefix.as_str()))
+        .map(|(prefix, replacement)| format!("{}{}", replacement, &url[prefix.len()..]))
+        .unwrap_or_else(|| url.to_string())
+}
+
+/// Removes any `user:password@` part from `url` so it can be recorded in vpm.toml or vpm.lock.
+pub fn strip_credentials(url: &str) -> String {
+    match url.split_once("://") {
+        Some((scheme, rest)) => {
+            let authority_end = rest.find('/').unwrap_or(rest.len());
+            match rest[..authority_end].rfind('@') {
+                Some(at) => format!("{}://{}", scheme, &rest[at + 1..]),
+                None => url.to_string(),
+            }
+        }
+        None => url.to_string(),
+    }
+}
+
+/// Returns the last path component of `url`, used to name checkouts of the repository.
+pub fn name_from_url(url: &str) -> &str {
+    url.rsplit('/').find(|&s| !s.is_empty()).unwrap_or_default()
+}
+
+fn url_host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let rest = rest.split_once('@').map_or(rest, |(_, host)| host);
+    rest.split(['/', ':']).next().unwrap_or(rest)
+}
+
+fn remote_callbacks(progress: Option<&ProgressBar>) -> RemoteCallbacks<'_> {
+    let mut callbacks = RemoteCallbacks::new();
+    // libgit2 keeps asking until a callback fails, so each source is only offered once.
+    let (mut tried_username, mut tried_agent, mut tried_token, mut tried_default) = (false, false, false, false);
+    callbacks.credentials(move |url, username, allowed| {
+        // SSH servers first ask for the user name alone when the URL does not carry one.
+        if allowed.contains(CredentialType::USERNAME) && !tried_username {
+            tried_username = true;
+            return Cred::username(username.unwrap_or("git"));
+        }
+        if allowed.contains(CredentialType::SSH_KEY) && !tried_agent {
+            tried_agent = true;
+            return Cred::ssh_key_from_agent(username.unwrap_or("git"));
+        }
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) && !tried_token {
+            tried_token = true;
+            if let Some((username, token)) = get_credentials(url_host(url)) {
+                return Cred::userpass_plaintext(&username, &token);
+            }
+        }
+        if allowed.contains(CredentialType::DEFAULT) && !tried_default {
+            tried_default = true;
+            return Cred::default();
+        }
+        Err(git2::Error::from_str(&format!(
+            "no valid credentials for '{}'. Load a key into your SSH agent or set an access token with `vpm config --token {}=<TOKEN>`",
+            url_host(url), url_host(url)
+        )))
+    });
+    if let Some(pb) = progress {
+        callbacks.transfer_progress(move |stats| {
+            if stats.received_objects() < stats.total_objects() {
+                pb.set_message(format!("Receiving objects: {}/{}", stats.received_objects(), stats.total_objects()));
+            } else {
+                pb.set_message(format!("Resolving deltas: {}/{}", stats.indexed_deltas(), stats.total_deltas()));
+            }
+            true
+        });
+    }
+    callbacks
+}
+
+fn fetch_options(progress: Option<&ProgressBar>, depth: i32) -> FetchOptions<'_> {
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(remote_callbacks(progress));
+    options.download_tags(AutotagOption::None);
+    options.depth(depth);
+    options
+}
+
+fn classify_error(error: git2::Error, url: &str, reference: Option<&str>) -> GitError {
+    let message = error.message().to_lowercase();
+    if error.code() == ErrorCode::Auth || message.contains("authentication") || message.contains("credentials") {
+        GitError::Auth { url: url.to_string(), message: error.message().to_string() }
+    } else if error.code() == ErrorCode::NotFound || message.contains("not our ref") || message.contains("couldn't find remote ref") || message.contains("unadvertised") {
+        GitError::UnknownRef { url: url.to_string(), reference: reference.unwrap_or("HEAD").to_string() }
+    } else if matches!(error.class(), ErrorClass::Net | ErrorClass::Http | ErrorClass::Ssl | ErrorClass::Ssh | ErrorClass::Os) {
+        GitError::Network { url: url.to_string(), message: error.message().to_string() }
+    } else {
+        GitError::Other(error)
+    }
+}
@@ -0,0 +1,69 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::sync::Mutex;
+use toml_edit::DocumentMut;
+
+/// A single `[[package]]` entry of vpm.lock.
+#[derive(Debug, Clone, Default)]
+pub struct LockEntry {
+    pub full_path: String,
+    pub source: String,
+    pub checksum: Option<String>,
+    /// Defines declared by the manifest the file was selected from, as `NAME` or `NAME=VALUE`.
+    pub defines: Vec<String>,
+    /// Paths the file pulls in with `` `include ``, relative to its directory.
+    pub includes: Vec<String>,
+    /// Vendored include directories declared by the manifest the file was selected from.
+    pub include_dirs: Vec<String>,
+    /// Modules the file was vendored for, when it was found by the modules it declares.
+    pub modules: Vec<String>,
+    /// Path of the file in its source repository, so that a choice between several files
+    /// declaring the same module is repeated on later runs.
+    pub origin: Option<String>,
+    /// Vendor primitives the file instantiates, left for the tools' own libraries.
+    pub blackboxes: Vec<String>,
+}
+
+static LOCKFILE_WRITES: Mutex<()> = Mutex::new(());
+
+/// Reads vpm.lock as text, applies `edit` and writes it back. Edits are applied one at a
+/// time, so modules vendored in parallel do not overwrite each other's entries.
+pub fn edit_lockfile(edit: impl FnOnce(&mut String) -> Result<()>) -> Result<()> {
+    let _guard = LOCKFILE_WRITES.lock().unwrap_or_else(|e| e.into_inner());
+    let mut lockfile = fs::read_to_string("vpm.lock").unwrap_or_default();
+    edit(&mut lockfile)?;
+    fs::write("vpm.lock", lockfile)?;
+    Ok(())
+}
+
+/// Reads every entry of vpm.lock. A missing lockfile has no entries.
+pub fn read_lockfile() -> Result<Vec<LockEntry>> {
+    let Ok(contents) = fs::read_to_string("vpm.lock") else {
+        return Ok(Vec::new());
+    };
+    parse_lockfile(&contents)
+}
+
+/// Parses the entries of a vpm.lock held in memory, such as inside `edit_lockfile`.
+pub fn parse_lockfile(contents: &str) -> Result<Vec<LockEntry>> {
+    let doc = contents.parse::<DocumentMut>().context("Failed to parse vpm.lock")?;
+    Ok(doc.get("package").and_then(|p| p.as_array_of_tables()).map_or_else(Vec::new, |packages| {
+        packages.iter().map(|entry| LockEntry {
+            full_path: entry.get("full_path").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+            source: entry.get("source").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+            checksum: entry.get("checksum").and_then(|v| v.as_str()).map(|s| s.to_string()),
+            defines: string_array(entry.get("defines")),
+            includes: string_array(entry.get("includes")),
+            include_dirs: string_array(entry.get("include_dirs")),
+            modules: string_array(entry.get("modules")),
+            origin: entry.get("origin").and_then(|v| v.as_str()).map(|s| s.to_string()),
+            blackboxes: string_array(entry.get("blackboxes")),
+        }).collect()
+    }))
+}
+
+fn string_array(item: Option<&toml_edit::Item>) -> Vec<String> {
+    item.and_then(|v| v.as_array())
+        .map(|a| a.iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect())
+        .unwrap_or_default()
+}
@@ -2,6 +2,16 @@ mod cmd;
 mod error;
 mod toml;
 mod config_man;
+mod git;
+mod registry;
+mod lockfile;
+mod vendor;
+mod manifest;
+mod targets;
+mod design;
+mod preprocess;
+mod primitives;
+mod stubs;
 
 use std::env;
 use std::io::{self, Write};
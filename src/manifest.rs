@@ -0,0 +1,408 @@
+use anyhow::{Context, Result};
+use serde_yaml::Value;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::design::{is_rtl, push_unique};
+use crate::git::{list_repo_files, materialize_files};
+
+/// Which tool an IP manifest was written for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestKind {
+    FuseSoc,
+    Bender,
+}
+
+/// The parts of a FuseSoC `.core` or Bender `Bender.yml` manifest that decide what to vendor.
+/// All paths are relative to the repository root.
+#[derive(Debug, Clone)]
+pub struct IpManifest {
+    pub kind: ManifestKind,
+    pub path: String,
+    pub name: String,
+    pub files: Vec<String>,
+    pub include_dirs: Vec<String>,
+    pub defines: Vec<(String, Option<String>)>,
+    pub dependencies: Vec<Dependency>,
+}
+
+/// A dependency of a manifest, either another manifest in the same repository or an
+/// external package vpm cannot resolve on its own.
+#[derive(Debug, Clone)]
+pub struct Dependency {
+    pub name: String,
+    pub source: Option<String>,
+}
+
+/// Files, include directories and defines needed by a module, gathered from the
+/// manifest that lists it and every manifest it depends on in the same repository.
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedManifest {
+    pub manifests: Vec<String>,
+    pub files: Vec<String>,
+    pub include_dirs: Vec<String>,
+    pub defines: Vec<(String, Option<String>)>,
+    pub external_dependencies: Vec<Dependency>,
+}
+
+/// Reads every FuseSoC and Bender manifest in the fetched repository at `repo_path`.
+/// Manifests that fail to parse are reported and skipped.
+pub fn find_manifests(repo_path: &Path) -> Result<Vec<IpManifest>> {
+    let manifest_paths: Vec<String> = list_repo_files(repo_path)?.into_iter()
+        .filter(|path| path.ends_with(".core") || path.ends_with("Bender.yml"))
+        .collect();
+    let files: Vec<PathBuf> = manifest_paths.iter().map(|path| repo_path.join(path)).collect();
+    materialize_files(repo_path, &files)?;
+
+    let mut manifests = Vec::new();
+    for path in manifest_paths {
+        let parsed = if path.ends_with(".core") {
+            parse_fusesoc_core(repo_path, &path)
+        } else {
+            parse_bender(repo_path, &path)
+        };
+        match parsed {
+            Ok(manifest) => manifests.push(manifest),
+            Err(e) => eprintln!("Warning: Skipping manifest '{}': {}", path, e),
+        }
+    }
+    Ok(manifests)
+}
+
+/// Finds the manifest that lists `module_file` (a repository path or a bare file name)
+/// and resolves its dependencies within `manifests`.
+pub fn resolve_module(manifests: &[IpManifest], module_file: &str) -> Option<ResolvedManifest> {
+    let matches_file = |file: &String| file == module_file || Path::new(file).file_name().is_some_and(|name| name == module_file);
+    let root = manifests.iter().find(|m| m.files.iter().any(matches_file))?;
+
+    let mut resolved = ResolvedManifest::default();
+    let mut queue = vec![root];
+    while let Some(manifest) = queue.pop() {
+        if resolved.manifests.contains(&manifest.path) {
+            continue;
+        }
+        resolved.manifests.push(manifest.path.clone());
+        manifest.files.iter().for_each(|file| push_unique(&mut resolved.files, file.clone()));
+        manifest.include_dirs.iter().for_each(|dir| push_unique(&mut resolved.include_dirs, dir.clone()));
+        manifest.defines.iter().for_each(|define| push_unique(&mut resolved.defines, define.clone()));
+        for dependency in &manifest.dependencies {
+            match manifests.iter().find(|m| m.kind == manifest.kind && vlnv_key(&m.name) == vlnv_key(&dependency.name)) {
+                Some(found) => queue.push(found),
+                None => if !resolved.external_dependencies.iter().any(|d| d.name == dependency.name) {
+                    resolved.external_dependencies.push(dependency.clone());
+                },
+            }
+        }
+    }
+    Some(resolved)
+}
+
+fn parse_fusesoc_core(repo_path: &Path, path: &str) -> Result<IpManifest> {
+    let doc: Value = serde_yaml::from_str(&fs::read_to_string(repo_path.join(path))?)
+        .context("Invalid YAML")?;
+    let core_dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    let name = doc.get("name").and_then(|v| v.as_str()).context("Missing core name")?.to_string();
+
+    // Only the filesets used by the default target are part of the design; testbenches
+    // and tool-specific filesets live in other targets.
+    let default_filesets: Option<Vec<String>> = doc.get("targets")
+        .and_then(|t| t.get("default"))
+        .and_then(|t| t.get("filesets"))
+        .and_then(|f| f.as_sequence())
+        .map(|filesets| filesets.iter().filter_map(|f| f.as_str()).filter(|f| !f.contains('?')).map(|f| f.to_string()).collect());
+
+    let mut manifest = IpManifest {
+        kind: ManifestKind::FuseSoc,
+        path: path.to_string(),
+        name,
+        files: Vec::new(),
+        include_dirs: Vec::new(),
+        defines: Vec::new(),
+        dependencies: Vec::new(),
+    };
+
+    if let Some(filesets) = doc.get("filesets").and_then(|f| f.as_mapping()) {
+        for (fileset_name, fileset) in filesets {
+            let fileset_name = fileset_name.as_str().unwrap_or_default();
+            if default_filesets.as_ref().is_some_and(|names| !names.iter().any(|n| n == fileset_name)) {
+                continue;
+            }
+            for file in fileset.get("files").and_then(|f| f.as_sequence()).into_iter().flatten() {
+                let (file_path, options) = match file {
+                    Value::String(file_path) => (file_path.as_str(), None),
+                    Value::Mapping(mapping) => match mapping.iter().next() {
+                        Some((Value::String(file_path), options)) => (file_path.as_str(), Some(options)),
+                        _ => continue,
+                    },
+                    _ => continue,
+                };
+                let full_path = join_relative(core_dir, file_path);
+                let is_include = options.and_then(|o| o.get("is_include_file")).and_then(|v| v.as_bool()).unwrap_or(false);
+                if is_include {
+                    let include_dir = options.and_then(|o| o.get("include_path")).and_then(|v| v.as_str())
+                        .map(|dir| join_relative(core_dir, dir))
+                        .unwrap_or_else(|| parent_dir(&full_path));
+                    push_unique(&mut manifest.include_dirs, include_dir);
+                }
+                if is_rtl(Path::new(&full_path)) {
+                    push_unique(&mut manifest.files, full_path);
+                }
+            }
+            for dependency in fileset.get("depend").and_then(|d| d.as_sequence()).into_iter().flatten().filter_map(|d| d.as_str()) {
+                if dependency.contains('?') {
+                    continue;
+                }
+                let name = dependency.trim_start_matches(['>', '<', '=', '~', '^', '!']).to_string();
+                manifest.dependencies.push(Dependency { name, source: None });
+            }
+        }
+    }
+
+    if let Some(parameters) = doc.get("parameters").and_then(|p| p.as_mapping()) {
+        for (name, parameter) in parameters {
+            if parameter.get("paramtype").and_then(|p| p.as_str()) != Some("vlogdefine") {
+                continue;
+            }
+            let name = name.as_str().unwrap_or_default().to_string();
+            match parameter.get("default") {
+                Some(Value::Bool(true)) => manifest.defines.push((name, None)),
+                Some(Value::Bool(false)) | None => {}
+                Some(value) => manifest.defines.push((name, Some(yaml_scalar(value)))),
+            }
+        }
+    }
+    Ok(manifest)
+}
+
+fn parse_bender(repo_path: &Path, path: &str) -> Result<IpManifest> {
+    let doc: Value = serde_yaml::from_str(&fs::read_to_string(repo_path.join(path))?)
+        .context("Invalid YAML")?;
+    let root = Path::new(path).parent().unwrap_or(Path::new(""));
+    let name = doc.get("package").and_then(|p| p.get("name")).and_then(|n| n.as_str())
+        .context("Missing package name")?.to_string();
+
+    let mut manifest = IpManifest {
+        kind: ManifestKind::Bender,
+        path: path.to_string(),
+        name,
+        files: Vec::new(),
+        include_dirs: Vec::new(),
+        defines: Vec::new(),
+        dependencies: Vec::new(),
+    };
+    for dir in doc.get("export_include_dirs").and_then(|d| d.as_sequence()).into_iter().flatten().filter_map(|d| d.as_str()) {
+        push_unique(&mut manifest.include_dirs, join_relative(root, dir));
+    }
+    if let Some(sources) = doc.get("sources") {
+        collect_bender_sources(root, sources, &mut manifest);
+    }
+
+    if let Some(dependencies) = doc.get("dependencies").and_then(|d| d.as_mapping()) {
+        for (name, spec) in dependencies {
+            let name = name.as_str().unwrap_or_default().to_string();
+            let source = match spec.get("path").and_then(|p| p.as_str()) {
+                // In-repository dependencies are resolved by package name.
+                Some(_) => None,
+                None => spec.get("git").and_then(|g| g.as_str()).map(|git| {
+                    let version = spec.get("rev").or_else(|| spec.get("version")).map(yaml_scalar);
+                    version.map_or_else(|| git.to_string(), |version| format!("{}@{}", git, version))
+                }).or_else(|| spec.as_str().map(|version| version.to_string())),
+            };
+            manifest.dependencies.push(Dependency { name, source });
+        }
+    }
+    Ok(manifest)
+}
+
+/// Walks a Bender `sources` entry, which is a file, a list, or a group of sources with
+/// its own target, include dirs and defines.
+fn collect_bender_sources(root: &Path, sources: &Value, manifest: &mut IpManifest) {
+    match sources {
+        Value::String(file) => {
+            let file = join_relative(root, file);
+            if is_rtl(Path::new(&file)) {
+                push_unique(&mut manifest.files, file);
+            }
+        }
+        Value::Sequence(items) => items.iter().for_each(|item| collect_bender_sources(root, item, manifest)),
+        Value::Mapping(group) => {
+            let target = group.get("target").map(yaml_scalar).unwrap_or_default();
+            if requires_simulation(&target) {
+                return;
+            }
+            for dir in group.get("include_dirs").and_then(|d| d.as_sequence()).into_iter().flatten().filter_map(|d| d.as_str()) {
+                push_unique(&mut manifest.include_dirs, join_relative(root, dir));
+            }
+            if let Some(defines) = group.get("defines").and_then(|d| d.as_mapping()) {
+                for (name, value) in defines {
+                    let define = (yaml_scalar(name), (!value.is_null()).then(|| yaml_scalar(value)));
+                    push_unique(&mut manifest.defines, define);
+                }
+            }
+            if let Some(files) = group.get("files") {
+                collect_bender_sources(root, files, manifest);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Whether the Bender target expression `target` only holds in test or simulation
+/// builds, e.g. `test`, `any(test, simulation)` or `all(simulation, xilinx)`. Negations
+/// such as `not(test)` never do.
+fn requires_simulation(target: &str) -> bool {
+    let target = target.trim();
+    let Some((operator, arguments)) = target.split_once('(') else {
+        return matches!(target, "test" | "simulation");
+    };
+    let arguments = split_arguments(arguments.strip_suffix(')').unwrap_or(arguments));
+    match operator.trim() {
+        "all" => arguments.iter().any(|argument| requires_simulation(argument)),
+        "any" => !arguments.is_empty() && arguments.iter().all(|argument| requires_simulation(argument)),
+        _ => false,
+    }
+}
+
+/// Splits the arguments of a target expression at the commas outside nested parentheses.
+fn split_arguments(arguments: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in arguments.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&arguments[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&arguments[start..]);
+    parts.into_iter().map(str::trim).filter(|part| !part.is_empty()).collect()
+}
+
+/// Compares FuseSoC names by vendor, library and name, ignoring the version.
+fn vlnv_key(name: &str) -> String {
+    name.split(':').take(3).collect::<Vec<_>>().join(":")
+}
+
+fn yaml_scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        other => serde_yaml::to_string(other).unwrap_or_default().trim().to_string(),
+    }
+}
+
+fn parent_dir(path: &str) -> String {
+    Path::new(path).parent().map(|p| p.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+/// Joins `relative` onto `base` and resolves `.` and `..` so the result is a plain
+/// repository path.
+fn join_relative(base: &Path, relative: &str) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    for component in base.join(relative).components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy().into_owned()),
+            Component::ParentDir => { parts.pop(); }
+            _ => {}
+        }
+    }
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fusesoc_core_uses_the_default_target() {
+        let repo = tempfile::tempdir().unwrap();
+        fs::create_dir_all(repo.path().join("ip")).unwrap();
+        fs::write(repo.path().join("ip/fifo.core"), r#"
+name: acme:ip:fifo:1.0
+filesets:
+  rtl:
+    files:
+      - rtl/fifo.sv
+      - rtl/defs.svh: {is_include_file: true}
+    depend:
+      - ">=acme:ip:ram:1.2"
+      - "?acme:ip:debug"
+  tb:
+    files:
+      - tb/fifo_tb.sv
+targets:
+  default:
+    filesets: [rtl]
+parameters:
+  WIDTH:
+    datatype: int
+    paramtype: vlogdefine
+    default: 8
+  SYNTHESIS:
+    datatype: bool
+    paramtype: vlogdefine
+    default: true
+  DEPTH:
+    datatype: int
+    paramtype: vlogparam
+    default: 4
+"#).unwrap();
+
+        let manifest = parse_fusesoc_core(repo.path(), "ip/fifo.core").unwrap();
+        assert_eq!(manifest.name, "acme:ip:fifo:1.0");
+        assert_eq!(manifest.files, ["ip/rtl/fifo.sv", "ip/rtl/defs.svh"]);
+        assert_eq!(manifest.include_dirs, ["ip/rtl"]);
+        assert_eq!(manifest.defines, [("WIDTH".to_string(), Some("8".to_string())), ("SYNTHESIS".to_string(), None)]);
+        let dependencies: Vec<&str> = manifest.dependencies.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(dependencies, ["acme:ip:ram:1.2"]);
+    }
+
+    #[test]
+    fn bender_skips_only_groups_for_test_or_simulation() {
+        let repo = tempfile::tempdir().unwrap();
+        fs::write(repo.path().join("Bender.yml"), r#"
+package:
+  name: axi
+export_include_dirs:
+  - include
+dependencies:
+  common_cells: { git: "https://github.com/pulp-platform/common_cells.git", version: 1.21.0 }
+  local_ip: { path: "ip/local" }
+sources:
+  - src/axi_pkg.sv
+  - target: test
+    files: [test/tb_axi.sv]
+  - target: any(test, simulation)
+    files: [test/axi_sim.sv]
+  - target: all(simulation, xilinx)
+    files: [test/axi_sim_xilinx.sv]
+  - target: not(test)
+    files: [src/axi_synth.sv]
+  - target: all(not(simulation), fpga)
+    defines: { FPGA: ~, DEPTH: 4 }
+    files: [src/axi_fpga.sv]
+  - target: any(simulation, asic)
+    files: [src/axi_asic.sv]
+"#).unwrap();
+
+        let manifest = parse_bender(repo.path(), "Bender.yml").unwrap();
+        assert_eq!(manifest.name, "axi");
+        assert_eq!(manifest.files, ["src/axi_pkg.sv", "src/axi_synth.sv", "src/axi_fpga.sv", "src/axi_asic.sv"]);
+        assert_eq!(manifest.include_dirs, ["include"]);
+        assert_eq!(manifest.defines, [("FPGA".to_string(), None), ("DEPTH".to_string(), Some("4".to_string()))]);
+        let dependencies: Vec<(&str, Option<&str>)> = manifest.dependencies.iter().map(|d| (d.name.as_str(), d.source.as_deref())).collect();
+        assert_eq!(dependencies, [("common_cells", Some("https://github.com/pulp-platform/common_cells.git@1.21.0")), ("local_ip", None)]);
+    }
+
+    #[test]
+    fn vlnv_key_ignores_the_version() {
+        assert_eq!(vlnv_key("acme:ip:fifo:1.0"), "acme:ip:fifo");
+        assert_eq!(vlnv_key("acme:ip:fifo"), "acme:ip:fifo");
+        assert_eq!(vlnv_key("acme:ip:fifo:1.0"), vlnv_key("acme:ip:fifo:2.1"));
+    }
+}
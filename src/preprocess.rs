@@ -0,0 +1,586 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How deep includes and macro expansions may nest before the source is assumed to be
+/// recursive.
+const MAX_DEPTH: usize = 64;
+
+/// A `` `define `` macro.
+#[derive(Debug, Clone)]
+struct Macro {
+    /// Formal arguments with their defaults, or `None` for a macro without parentheses.
+    params: Option<Vec<(String, Option<String>)>>,
+    body: String,
+}
+
+/// The macros defined at one point of preprocessing.
+#[derive(Debug, Clone, Default)]
+pub struct DefineSet {
+    macros: HashMap<String, Macro>,
+}
+
+impl DefineSet {
+    /// Builds a define set from `NAME` or `NAME=VALUE` pairs, as given on a command line.
+    pub fn from_defines(defines: &[(String, Option<String>)]) -> DefineSet {
+        let macros = defines.iter()
+            .map(|(name, value)| (name.clone(), Macro { params: None, body: value.clone().unwrap_or_default() }))
+            .collect();
+        DefineSet { macros }
+    }
+}
+
+/// Splits a `NAME=VALUE` command line define.
+pub fn parse_define(define: &str) -> (String, Option<String>) {
+    match define.split_once('=') {
+        Some((name, value)) => (name.to_string(), Some(value.to_string())),
+        None => (define.to_string(), None),
+    }
+}
+
+/// Source text after preprocessing.
+#[derive(Debug, Clone, Default)]
+pub struct Preprocessed {
+    pub text: String,
+    /// Paths named by the `` `include `` directives that were active, as written, including
+    /// those of included files. Includes that could not be found are listed too.
+    pub includes: Vec<String>,
+}
+
+/// Builds the define set that dependency analysis preprocesses sources with, so that it
+/// only sees the code that configuration compiles.
+pub fn configure_defines(defines: &[(String, Option<String>)]) -> DefineSet {
+    let names: Vec<String> = defines.iter()
+        .map(|(name, value)| value.as_ref().map_or_else(|| name.clone(), |value| format!("{}={}", name, value)))
+        .collect();
+    println!("Resolving dependencies with defines: {}", if names.is_empty() { "(none)".to_string() } else { names.join(", ") });
+    DefineSet::from_defines(defines)
+}
+
+/// Runs the Verilog preprocessor over `contents`: evaluates `` `define ``, `` `undef ``,
+/// `` `ifdef ``/`` `ifndef ``/`` `elsif ``/`` `else `` and `` `endif ``, inlines includes found
+/// in `include_dirs` and expands macros. Inactive code is blanked out but line numbers are
+/// kept.
+pub fn preprocess(contents: &str, defines: &DefineSet, include_dirs: &[PathBuf]) -> Result<Preprocessed> {
+    let mut preprocessor = Preprocessor {
+        defines: defines.clone(),
+        include_dirs: include_dirs.to_vec(),
+        includes: Vec::new(),
+        depth: 0,
+    };
+    let text = preprocessor.run(contents)?;
+    Ok(Preprocessed { text, includes: preprocessor.includes })
+}
+
+struct Preprocessor {
+    defines: DefineSet,
+    include_dirs: Vec<PathBuf>,
+    includes: Vec<String>,
+    depth: usize,
+}
+
+/// One level of `` `ifdef `` nesting.
+struct Conditional {
+    /// Whether the current branch is compiled.
+    active: bool,
+    /// Whether an earlier branch of this conditional was taken.
+    taken: bool,
+    /// Whether the enclosing code is compiled.
+    enclosing: bool,
+}
+
+impl Preprocessor {
+    fn run(&mut self, source: &str) -> Result<String> {
+        self.depth += 1;
+        anyhow::ensure!(self.depth <= MAX_DEPTH, "Includes or macros nest more than {} levels deep", MAX_DEPTH);
+        let chars: Vec<char> = source.chars().collect();
+        let mut output = String::with_capacity(source.len());
+        let mut conditionals: Vec<Conditional> = Vec::new();
+        let mut i = 0;
+
+        while i < chars.len() {
+            let active = conditionals.last().is_none_or(|c| c.active);
+            let c = chars[i];
+            let next = chars.get(i + 1).copied();
+
+            // Comments and strings are copied as they are; directives inside them do nothing.
+            let literal_end = match (c, next) {
+                ('/', Some('/')) => Some(find_from(&chars, i, "\n").unwrap_or(chars.len())),
+                ('/', Some('*')) => Some(find_from(&chars, i + 2, "*/").map_or(chars.len(), |end| end + 2)),
+                ('"', _) => Some(string_end(&chars, i)),
+                _ => None,
+            };
+            if let Some(end) = literal_end {
+                emit(&mut output, &chars[i..end], active);
+                i = end;
+                continue;
+            }
+            if c != '`' {
+                emit(&mut output, &chars[i..i + 1], active);
+                i += 1;
+                continue;
+            }
+
+            let (name, after_name) = read_identifier(&chars, i + 1);
+            match name.as_str() {
+                "ifdef" | "ifndef" => {
+                    let (macro_name, end) = read_identifier(&chars, skip_spaces(&chars, after_name));
+                    let defined = self.defines.macros.contains_key(&macro_name);
+                    let taken = defined == (name == "ifdef");
+                    conditionals.push(Conditional { active: active && taken, taken, enclosing: active });
+                    i = end;
+                }
+                "elsif" => {
+                    let (macro_name, end) = read_identifier(&chars, skip_spaces(&chars, after_name));
+                    if let Some(conditional) = conditionals.last_mut() {
+                        let taken = !conditional.taken && self.defines.macros.contains_key(&macro_name);
+                        conditional.active = conditional.enclosing && taken;
+                        conditional.taken |= taken;
+                    }
+                    i = end;
+                }
+                "else" => {
+                    if let Some(conditional) = conditionals.last_mut() {
+                        conditional.active = conditional.enclosing && !conditional.taken;
+                        conditional.taken = true;
+                    }
+                    i = after_name;
+                }
+                "endif" => {
+                    conditionals.pop();
+                    i = after_name;
+                }
+                "define" => {
+                    let (macro_name, after_macro_name) = read_identifier(&chars, skip_spaces(&chars, after_name));
+                    let (params, after_params) = if chars.get(after_macro_name) == Some(&'(') {
+                        let end = matching_paren(&chars, after_macro_name);
+                        let params = split_arguments(&chars[after_macro_name + 1..end.saturating_sub(1)])
+                            .into_iter()
+                            .map(|param| match param.split_once('=') {
+                                Some((name, default)) => (name.trim().to_string(), Some(default.trim().to_string())),
+                                None => (param.trim().to_string(), None),
+                            })
+                            .collect();
+                        (Some(params), end)
+                    } else {
+                        (None, after_macro_name)
+                    };
+                    let (body, end) = read_macro_body(&chars, after_params);
+                    if active && !macro_name.is_empty() {
+                        self.defines.macros.insert(macro_name, Macro { params, body: body.clone() });
+                    }
+                    // Keep the line count of multi-line definitions; the body is trimmed, so
+                    // the continued lines are counted in the source.
+                    output.extend(std::iter::repeat_n('\n', chars[after_params..end].iter().filter(|c| **c == '\n').count()));
+                    i = end;
+                }
+                "undef" => {
+                    let (macro_name, end) = read_identifier(&chars, skip_spaces(&chars, after_name));
+                    if active {
+                        self.defines.macros.remove(&macro_name);
+                    }
+                    i = end;
+                }
+                "include" => {
+                    let start = skip_spaces(&chars, after_name);
+                    let close = match chars.get(start) {
+                        Some('"') => '"',
+                        Some('<') => '>',
+                        _ => {
+                            i = after_name;
+                            continue;
+                        }
+                    };
+                    let end = chars[start + 1..].iter().position(|&c| c == close || c == '\n').map_or(chars.len(), |p| start + 1 + p);
+                    let path: String = chars[start + 1..end].iter().collect();
+                    i = (end + 1).min(chars.len());
+                    if !active || path.is_empty() {
+                        continue;
+                    }
+                    if !self.includes.contains(&path) {
+                        self.includes.push(path.clone());
+                    }
+                    if let Some(file) = self.find_include(&path) {
+                        let included = fs::read_to_string(&file).unwrap_or_default();
+                        let text = self.run(&included)?;
+                        // Keep the including file's line numbers by folding the include onto one line.
+                        output.push_str(&text.replace('\n', " "));
+                    }
+                }
+                "__LINE__" => {
+                    let line = chars[..i].iter().filter(|&&c| c == '\n').count() + 1;
+                    emit_str(&mut output, &line.to_string(), active);
+                    i = after_name;
+                }
+                _ => match self.defines.macros.get(&name).cloned() {
+                    Some(definition) if active => {
+                        let (expansion, end) = self.expand(&definition, &chars, after_name)?;
+                        output.push_str(&expansion);
+                        i = end;
+                    }
+                    // Other directives (`timescale, `default_nettype, ...) and unknown macros
+                    // are left for the tools.
+                    _ => {
+                        emit(&mut output, &chars[i..after_name.max(i + 1)], active);
+                        i = after_name.max(i + 1);
+                    }
+                },
+            }
+        }
+        self.depth -= 1;
+        Ok(output)
+    }
+
+    /// Expands a use of `definition` whose name ends at `after_name`. Returns the expansion
+    /// and where the use ends.
+    fn expand(&mut self, definition: &Macro, chars: &[char], after_name: usize) -> Result<(String, usize)> {
+        let mut body = definition.body.clone();
+        let mut end = after_name;
+        if let Some(params) = &definition.params {
+            let open = skip_whitespace(chars, after_name);
+            let arguments = if chars.get(open) == Some(&'(') {
+                end = matching_paren(chars, open);
+                split_arguments(&chars[open + 1..end.saturating_sub(1)])
+            } else {
+                Vec::new()
+            };
+            let values: HashMap<&str, String> = params.iter().enumerate()
+                .map(|(index, (name, default))| {
+                    let value = arguments.get(index).map(|a| a.trim().to_string()).filter(|a| !a.is_empty())
+                        .or_else(|| default.clone())
+                        .unwrap_or_default();
+                    (name.as_str(), value)
+                })
+                .collect();
+            body = substitute(&body, &values);
+        }
+        // `` pastes tokens together and `" quotes inside macro bodies.
+        let body = body.replace("``", "").replace("`\\`\"", "\\\"").replace("`\"", "\"");
+        let expansion = self.run(&body)?;
+        Ok((expansion.replace('\n', " "), end))
+    }
+
+    fn find_include(&self, path: &str) -> Option<PathBuf> {
+        let path = Path::new(path);
+        if path.is_absolute() {
+            return path.is_file().then(|| path.to_path_buf());
+        }
+        self.include_dirs.iter().map(|dir| dir.join(path)).find(|candidate| candidate.is_file())
+    }
+}
+
+/// Copies `chars` when the code is compiled, and only its line breaks otherwise.
+fn emit(output: &mut String, chars: &[char], active: bool) {
+    if active {
+        output.extend(chars);
+    } else {
+        output.extend(chars.iter().filter(|&&c| c == '\n'));
+    }
+}
+
+fn emit_str(output: &mut String, text: &str, active: bool) {
+    if active {
+        output.push_str(text);
+    }
+}
+
+fn find_from(chars: &[char], start: usize, pattern: &str) -> Option<usize> {
+    let pattern: Vec<char> = pattern.chars().collect();
+    (start..chars.len()).find(|&i| chars[i..].starts_with(&pattern))
+}
+
+/// Index just past the string literal starting at `start`.
+fn string_end(chars: &[char], start: usize) -> usize {
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            '"' | '\n' => return i + 1,
+            _ => i += 1,
+        }
+    }
+    chars.len()
+}
+
+fn read_identifier(chars: &[char], start: usize) -> (String, usize) {
+    let mut end = start;
+    while end < chars.len() && (chars[end].is_ascii_alphanumeric() || chars[end] == '_' || chars[end] == '$') {
+        end += 1;
+    }
+    (chars[start..end].iter().collect(), end)
+}
+
+fn skip_spaces(chars: &[char], mut i: usize) -> usize {
+    while i < chars.len() && (chars[i] == ' ' || chars[i] == '\t') {
+        i += 1;
+    }
+    i
+}
+
+fn skip_whitespace(chars: &[char], mut i: usize) -> usize {
+    while i < chars.len() && chars[i].is_whitespace() {
+        i += 1;
+    }
+    i
+}
+
+/// Index just past the parenthesis that closes the one at `open`.
+fn matching_paren(chars: &[char], open: usize) -> usize {
+    let mut depth = 0;
+    let mut i = open;
+    while i < chars.len() {
+        match chars[i] {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            '"' => {
+                i = string_end(chars, i);
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    chars.len()
+}
+
+/// Splits macro arguments on the commas that are not nested in brackets or strings.
+fn split_arguments(chars: &[char]) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            '"' => {
+                let end = string_end(chars, i);
+                current.extend(&chars[i..end]);
+                i = end;
+                continue;
+            }
+            ',' if depth == 0 => {
+                arguments.push(std::mem::take(&mut current));
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+        current.push(chars[i]);
+        i += 1;
+    }
+    if !current.trim().is_empty() || !arguments.is_empty() {
+        arguments.push(current);
+    }
+    arguments
+}
+
+/// The body of a `` `define `` starting at `start`: the rest of the line, continued by a
+/// trailing backslash, without a trailing `//` comment.
+fn read_macro_body(chars: &[char], start: usize) -> (String, usize) {
+    let mut body = String::new();
+    let mut i = start;
+    while i < chars.len() {
+        match (chars[i], chars.get(i + 1)) {
+            ('\\', Some('\n')) => {
+                body.push('\n');
+                i += 2;
+            }
+            ('\\', Some('\r')) if chars.get(i + 2) == Some(&'\n') => {
+                body.push('\n');
+                i += 3;
+            }
+            ('\n', _) => break,
+            ('/', Some('/')) => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                break;
+            }
+            ('"', _) => {
+                let end = string_end(chars, i);
+                body.extend(&chars[i..end]);
+                i = end;
+            }
+            (c, _) => {
+                body.push(c);
+                i += 1;
+            }
+        }
+    }
+    (body.trim().to_string(), i)
+}
+
+/// Replaces every identifier in `body` that names a macro argument with its value.
+fn substitute(body: &str, values: &HashMap<&str, String>) -> String {
+    let chars: Vec<char> = body.chars().collect();
+    let mut output = String::with_capacity(body.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let starts_identifier = (c.is_ascii_alphabetic() || c == '_') && (i == 0 || !(chars[i - 1].is_ascii_alphanumeric() || chars[i - 1] == '_' || chars[i - 1] == '$'));
+        if starts_identifier {
+            let (identifier, end) = read_identifier(&chars, i);
+            match values.get(identifier.as_str()) {
+                Some(value) => output.push_str(value),
+                None => output.push_str(&identifier),
+            }
+            i = end;
+        } else {
+            output.push(c);
+            i += 1;
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(contents: &str, defines: &[&str]) -> Preprocessed {
+        let defines: Vec<(String, Option<String>)> = defines.iter().map(|d| parse_define(d)).collect();
+        preprocess(contents, &DefineSet::from_defines(&defines), &[]).unwrap()
+    }
+
+    /// The non-blank lines of `text`, trimmed.
+    fn code(text: &str) -> Vec<&str> {
+        text.lines().map(str::trim).filter(|line| !line.is_empty()).collect()
+    }
+
+    #[test]
+    fn nested_conditionals_inside_inactive_branches_stay_inactive() {
+        let source = "\
+`ifdef FPGA
+  `ifdef ASIC
+    wire both;
+  `elsif SIM
+    wire fpga_sim;
+  `else
+    wire fpga_only;
+  `endif
+`elsif ASIC
+  `ifdef SIM
+    wire asic_sim;
+  `else
+    wire asic_only;
+  `endif
+`else
+  wire neither;
+`endif
+";
+        assert_eq!(code(&run(source, &["FPGA"]).text), ["wire fpga_only;"]);
+        assert_eq!(code(&run(source, &["FPGA", "SIM"]).text), ["wire fpga_sim;"]);
+        // SIM is defined, but the FPGA branch holding `elsif SIM is not taken.
+        assert_eq!(code(&run(source, &["ASIC", "SIM"]).text), ["wire asic_sim;"]);
+        assert_eq!(code(&run(source, &["SIM"]).text), ["wire neither;"]);
+    }
+
+    #[test]
+    fn ifndef_and_undef() {
+        let source = "\
+`define FAST
+`undef FAST
+`ifndef FAST
+  wire slow;
+`endif
+`ifdef FAST
+  wire fast;
+`endif
+";
+        assert_eq!(code(&run(source, &["FAST"]).text), ["wire slow;"]);
+    }
+
+    #[test]
+    fn macros_with_default_arguments() {
+        let source = "\
+`define REG(name, width = 8, reset = 0) reg [width-1:0] name = reset;
+`REG(a)
+`REG(b, 4)
+`REG(c, , 1)
+";
+        assert_eq!(code(&run(source, &[]).text), [
+            "reg [8-1:0] a = 0;",
+            "reg [4-1:0] b = 0;",
+            "reg [8-1:0] c = 1;",
+        ]);
+    }
+
+    #[test]
+    fn stringification_and_token_pasting() {
+        let source = "\
+`define NAME(x) `\"x`\"
+`define INST(kind, n) kind u_``kind``_``n ();
+initial $display(`NAME(hello));
+`INST(fifo, 0)
+";
+        assert_eq!(code(&run(source, &[]).text), [
+            "initial $display(\"hello\");",
+            "fifo u_fifo_0 ();",
+        ]);
+    }
+
+    #[test]
+    fn escaped_quotes_inside_stringification() {
+        let source = "\
+`define MSG(x) `\"x says `\\`\"hi`\\`\"`\"
+initial $display(`MSG(dut));
+";
+        assert_eq!(code(&run(source, &[]).text), ["initial $display(\"dut says \\\"hi\\\"\");"]);
+    }
+
+    #[test]
+    fn command_line_defines_are_expanded() {
+        let source = "wire [`WIDTH-1:0] data;\n";
+        assert_eq!(code(&run(source, &["WIDTH=16"]).text), ["wire [16-1:0] data;"]);
+    }
+
+    #[test]
+    fn includes_are_inlined_and_recorded() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("inc")).unwrap();
+        fs::write(dir.path().join("inc/cfg.vh"), "`define DEPTH 32\n`include \"types.vh\"\n").unwrap();
+        fs::write(dir.path().join("inc/types.vh"), "typedef logic [7:0] byte_t;\n").unwrap();
+        let source = "`include \"cfg.vh\"\nwire [`DEPTH-1:0] d;\n";
+
+        let preprocessed = preprocess(source, &DefineSet::default(), &[dir.path().join("inc")]).unwrap();
+        assert_eq!(code(&preprocessed.text), ["typedef logic [7:0] byte_t;", "wire [32-1:0] d;"]);
+        assert_eq!(preprocessed.includes, ["cfg.vh", "types.vh"]);
+    }
+
+    #[test]
+    fn missing_includes_are_still_recorded() {
+        let preprocessed = run("`include \"missing.vh\"\nwire a;\n", &[]);
+        assert_eq!(preprocessed.includes, ["missing.vh"]);
+        assert_eq!(code(&preprocessed.text), ["wire a;"]);
+    }
+
+    #[test]
+    fn line_numbers_are_preserved() {
+        let source = "\
+`define MULTI(a) \\
+  a
+`ifdef NOPE
+wire skipped;
+/* a comment
+   over lines */
+`else
+wire kept;
+`endif
+wire last; // `__LINE__
+";
+        let text = run(source, &[]).text;
+        assert_eq!(text.lines().count(), source.lines().count());
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[7].trim(), "wire kept;");
+        assert_eq!(lines[9].trim(), "wire last; // `__LINE__");
+        // Comments in inactive branches are blanked with the code around them.
+        assert!(lines[3..7].iter().all(|line| line.trim().is_empty()));
+    }
+}
@@ -0,0 +1,68 @@
+use once_cell::sync::Lazy;
+use std::path::Path;
+
+use crate::toml::get_primitives;
+
+/// Cells provided by FPGA vendor libraries and standard cell libraries rather than by any
+/// repository. A trailing `*` matches any suffix.
+const BUILTIN_PRIMITIVES: &[(&str, &[&str])] = &[
+    ("Xilinx", &[
+        "BUFG", "BUFGCE", "BUFGCE_DIV", "BUFGCTRL", "BUFGMUX", "BUFGMUX_CTRL", "BUFG_GT", "BUFH", "BUFHCE", "BUFIO", "BUFMR", "BUFR",
+        "IBUF", "IBUFG", "IBUFDS", "IBUFGDS", "IBUFDS_GTE2", "IBUFDS_GTE3", "IBUFDS_GTE4", "IBUFDS_DIFF_OUT",
+        "OBUF", "OBUFT", "OBUFDS", "OBUFTDS", "OBUFDS_GTE3", "OBUFDS_GTE4", "IOBUF", "IOBUFDS",
+        "IDDR", "IDDR_2CLK", "IDDRE1", "ODDR", "ODDRE1", "IDELAYCTRL", "IDELAYE2", "IDELAYE3", "ODELAYE2", "ODELAYE3",
+        "ISERDESE2", "ISERDESE3", "OSERDESE2", "OSERDESE3",
+        "MMCME2_ADV", "MMCME2_BASE", "MMCME3_ADV", "MMCME3_BASE", "MMCME4_ADV", "MMCME4_BASE",
+        "PLLE2_ADV", "PLLE2_BASE", "PLLE3_ADV", "PLLE3_BASE", "PLLE4_ADV", "PLLE4_BASE",
+        "RAMB18E1", "RAMB36E1", "RAMB18E2", "RAMB36E2", "FIFO18E1", "FIFO36E1", "FIFO18E2", "FIFO36E2", "URAM288", "URAM288_BASE",
+        "RAM32M", "RAM64M", "RAM32X1D", "RAM64X1D", "RAM128X1D", "RAM256X1S", "ROM128X1", "ROM256X1",
+        "DSP48E1", "DSP48E2", "SRL16E", "SRLC32E", "LUT1", "LUT2", "LUT3", "LUT4", "LUT5", "LUT6", "LUT6_2",
+        "FDCE", "FDPE", "FDRE", "FDSE", "LDCE", "LDPE", "CARRY4", "CARRY8", "MUXF7", "MUXF8", "MUXF9",
+        "STARTUPE2", "STARTUPE3", "ICAPE2", "ICAPE3", "BSCANE2", "DNA_PORT", "XADC", "SYSMONE1", "SYSMONE4",
+        "GTPE2_*", "GTXE2_*", "GTHE2_*", "GTHE3_*", "GTHE4_*", "GTYE3_*", "GTYE4_*", "PCIE_2_1", "PCIE_3_0", "PCIE3_*", "PCIE40E4", "GND", "VCC",
+    ]),
+    ("Intel", &[
+        "altsyncram", "altera_syncram", "altdpram", "altshift_taps", "altpll", "altera_pll", "altclkctrl",
+        "altddio_in", "altddio_out", "altddio_bidir", "altiobuf_in", "altiobuf_out", "altiobuf_bidir",
+        "altlvds_rx", "altlvds_tx", "altmult_add", "altmult_accum", "altsquare", "altera_std_synchronizer",
+        "dcfifo", "dcfifo_mixed_widths", "scfifo", "lpm_*",
+        "cyclone10lp_*", "cycloneive_*", "cyclonev_*", "arriav_*", "arria10_*", "stratixv_*", "twentynm_*", "fourteennm_*", "tennm_*",
+    ]),
+    ("Lattice", &[
+        "SB_*", "EHXPLLL", "EHXPLLJ", "DP16KD", "PDPW16KD", "DCUA", "IDDRX1F", "ODDRX1F", "IDDRX2F", "ODDRX2F",
+        "TRELLIS_IO", "OSCG", "OSCH", "USRMCLK", "GSR", "PUR",
+    ]),
+    ("standard cell", &[
+        "sky130_fd_sc_*", "sky130_fd_io__*", "sky130_sram_*", "gf180mcu_fd_sc_*", "gf180mcu_fd_io__*", "sg13g2_*",
+    ]),
+];
+
+/// Extra primitives from `[resolve] primitives` in vpm.toml.
+static PROJECT_PRIMITIVES: Lazy<Vec<String>> = Lazy::new(|| {
+    if Path::new("vpm.toml").exists() { get_primitives() } else { Vec::new() }
+});
+
+fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => pattern == name,
+    }
+}
+
+/// The library `name` belongs to if it is a known vendor primitive or one listed in vpm.toml.
+pub fn primitive_library(name: &str) -> Option<&'static str> {
+    BUILTIN_PRIMITIVES.iter()
+        .find(|(_, patterns)| patterns.iter().any(|pattern| matches(pattern, name)))
+        .map(|(library, _)| *library)
+        .or_else(|| PROJECT_PRIMITIVES.iter().any(|pattern| matches(pattern, name)).then_some("project"))
+}
+
+/// Splits module names into those to fetch and the primitives to leave as black boxes.
+pub fn split_primitives(modules: Vec<String>) -> (Vec<String>, Vec<String>) {
+    modules.into_iter().partition(|module| primitive_library(module).is_none())
+}
+
+/// The filelist comment listing `blackboxes`, which the tools take from their own libraries.
+pub fn blackbox_comment(blackboxes: &[String]) -> Option<String> {
+    (!blackboxes.is_empty()).then(|| format!("// Black boxes from vendor libraries: {}", blackboxes.join(" ")))
+}
@@ -0,0 +1,244 @@
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use toml_edit::{Array, ArrayOfTables, DocumentMut, Item, Table, Value};
+
+use crate::config_man::get_registry_index;
+use crate::git::clone_repo;
+use crate::vendor::VendorArchive;
+
+/// A single published version of a package, as listed in the registry index.
+#[derive(Debug, Clone)]
+pub struct PackageVersion {
+    pub name: String,
+    pub version: String,
+    pub source: String,
+    pub checksum: String,
+    pub top_modules: Vec<String>,
+}
+
+/// Returns a local directory holding the registry index. Indexes that live in a git
+/// repository are fetched into the cache directory first.
+pub fn open_index(index: &str) -> Result<PathBuf> {
+    let index = index.strip_prefix("file://").unwrap_or(index);
+    if index.contains("://") || index.starts_with("git@") {
+        let cache_dir = crate::config_man::cache_dir()?.join("registry-index");
+        clone_repo(index, &cache_dir, None, false, None)
+            .with_context(|| format!("Failed to fetch registry index from '{}'", index))?;
+        Ok(cache_dir)
+    } else {
+        Ok(PathBuf::from(index))
+    }
+}
+
+fn index_file(index_dir: &Path, name: &str) -> PathBuf {
+    index_dir.join("packages").join(format!("{}.toml", name))
+}
+
+/// Lists every published version of `name`, oldest first.
+pub fn get_versions(index_dir: &Path, name: &str) -> Result<Vec<PackageVersion>> {
+    let path = index_file(index_dir, name);
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("Package '{}' not found in registry index '{}'", name, index_dir.display()))?;
+    let doc = contents.parse::<DocumentMut>()
+        .with_context(|| format!("Failed to parse registry entry '{}'", path.display()))?;
+    let versions = doc.get("versions").and_then(|v| v.as_array_of_tables()).map_or_else(Vec::new, |versions| {
+        versions.iter().map(|entry| PackageVersion {
+            name: name.to_string(),
+            version: entry.get("version").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+            source: entry.get("source").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+            checksum: entry.get("checksum").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+            top_modules: entry.get("top_modules").and_then(|v| v.as_array())
+                .map(|a| a.iter().filter_map(|m| m.as_str().map(|s| s.to_string())).collect())
+                .unwrap_or_default(),
+        }).collect()
+    });
+    Ok(versions)
+}
+
+/// Lists the names of every package in the index at `index_dir`.
+pub fn list_packages(index_dir: &Path) -> Result<Vec<String>> {
+    let mut names: Vec<String> = fs::read_dir(index_dir.join("packages"))
+        .with_context(|| format!("Failed to read registry index '{}'", index_dir.display()))?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().to_str()?.strip_suffix(".toml").map(|s| s.to_string()))
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Finds `name` at `version` (or its latest published version) in the configured registry,
+/// or in the registry bundled with `archive`.
+pub fn resolve(name: &str, version: Option<&str>, archive: Option<&VendorArchive>) -> Result<(PathBuf, PackageVersion)> {
+    let index_dir = match archive {
+        Some(archive) => archive.registry_dir(),
+        None => open_index(&get_registry_index())?,
+    };
+    let versions = get_versions(&index_dir, name)?;
+    let package = match version {
+        Some(version) => versions.into_iter().find(|v| v.version == version)
+            .with_context(|| format!("Version '{}' of package '{}' not found in the registry", version, name))?,
+        None => versions.into_iter()
+            .filter_map(|v| version_key(&v.version).map(|key| (key, v)))
+            .max_by(|(a, _), (b, _)| a.cmp(b))
+            .map(|(_, v)| v)
+            .with_context(|| format!("Package '{}' has no published versions", name))?,
+    };
+    Ok((index_dir, package))
+}
+
+/// Orders `MAJOR.MINOR.PATCH[-PRERELEASE][+BUILD]` versions by semver precedence, with
+/// pre-releases before their release. Returns `None` for anything else.
+fn version_key(version: &str) -> Option<(u64, u64, u64, bool, String)> {
+    let version = version.split('+').next().unwrap_or(version);
+    let (release, prerelease) = match version.split_once('-') {
+        Some((release, prerelease)) => (release, Some(prerelease)),
+        None => (version, None),
+    };
+    let numbers: Vec<u64> = release.split('.').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+    let [major, minor, patch] = numbers[..] else { return None };
+    Some((major, minor, patch, prerelease.is_none(), prerelease.unwrap_or_default().to_string()))
+}
+
+/// Downloads (or reads from the index directory) the archive of `package`.
+pub async fn fetch_archive(index_dir: &Path, package: &PackageVersion) -> Result<Vec<u8>> {
+    if package.source.starts_with("http://") || package.source.starts_with("https://") {
+        let response = reqwest::get(&package.source).await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("Failed to download '{}'", package.source))?;
+        Ok(response.bytes().await?.to_vec())
+    } else {
+        let source = package.source.strip_prefix("file://").unwrap_or(&package.source);
+        fs::read(index_dir.join(source)).with_context(|| format!("Failed to read archive '{}'", source))
+    }
+}
+
+/// Verifies the checksum of a fetched `archive` of `package` and unpacks it into `destination`.
+pub fn install_package(package: &PackageVersion, archive: &[u8], destination: &Path) -> Result<()> {
+    let checksum = sha256_checksum(archive);
+    anyhow::ensure!(
+        checksum == package.checksum,
+        "Checksum mismatch for {}@{}: expected '{}', got '{}'", package.name, package.version, package.checksum, checksum
+    );
+
+    unpack_archive(archive, destination)
+}
+
+/// Reads every file of a package archive as `(path, contents)`, with paths relative to
+/// the package root.
+pub fn archive_files(archive: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut files = Vec::new();
+    let mut tarball = tar::Archive::new(GzDecoder::new(archive));
+    for entry in tarball.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.components().skip(1).collect::<PathBuf>();
+        let mut contents = Vec::new();
+        std::io::Read::read_to_end(&mut entry, &mut contents)?;
+        files.push((path.to_string_lossy().into_owned(), contents));
+    }
+    Ok(files)
+}
+
+/// Unpacks a gzipped tarball rooted at a single top-level directory into `destination`,
+/// replacing anything already there.
+pub fn unpack_archive(archive: &[u8], destination: &Path) -> Result<()> {
+    if destination.exists() {
+        fs::remove_dir_all(destination)?;
+    }
+    fs::create_dir_all(destination)?;
+    let mut tarball = tar::Archive::new(GzDecoder::new(archive));
+    for entry in tarball.entries()? {
+        let mut entry = entry?;
+        // Links could point the next entry outside `destination`; packages only need regular files.
+        let kind = entry.header().entry_type();
+        if kind.is_symlink() || kind.is_hard_link() {
+            continue;
+        }
+        // Archives are rooted at a single directory; strip it so files land directly in `destination`.
+        let path = entry.path()?.components().skip(1).collect::<PathBuf>();
+        if path.as_os_str().is_empty() {
+            continue;
+        }
+        anyhow::ensure!(
+            path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)),
+            "Archive entry '{}' escapes the destination", path.display()
+        );
+        let target = destination.join(&path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        entry.unpack(&target)?;
+    }
+    Ok(())
+}
+
+/// Packs `files` (paths relative to `root`) into `<index>/archives/<name>-<version>.tar.gz`
+/// and adds the new version to the package's index entry.
+pub fn publish(index_dir: &Path, package: &PackageVersion, description: &str, license: &str, root: &Path, files: &[PathBuf]) -> Result<PackageVersion> {
+    let entry_path = index_file(index_dir, &package.name);
+    if entry_path.exists() && get_versions(index_dir, &package.name)?.iter().any(|v| v.version == package.version) {
+        anyhow::bail!("Version '{}' of package '{}' is already published", package.version, package.name);
+    }
+
+    let prefix = format!("{}-{}", package.name, package.version);
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    for file in files {
+        builder.append_path_with_name(root.join(file), Path::new(&prefix).join(file))
+            .with_context(|| format!("Failed to add '{}' to the archive", file.display()))?;
+    }
+    let archive = builder.into_inner()?.finish()?;
+
+    let source = format!("archives/{}.tar.gz", prefix);
+    fs::create_dir_all(index_dir.join("archives"))?;
+    fs::write(index_dir.join(&source), &archive)?;
+
+    let published = PackageVersion { source, checksum: sha256_checksum(&archive), ..package.clone() };
+    add_index_entry(index_dir, &published, description, license)?;
+    Ok(published)
+}
+
+/// Appends `package` to its entry in the index at `index_dir`, creating the entry if needed.
+pub fn add_index_entry(index_dir: &Path, package: &PackageVersion, description: &str, license: &str) -> Result<()> {
+    let entry_path = index_file(index_dir, &package.name);
+    let mut doc = fs::read_to_string(&entry_path).unwrap_or_default().parse::<DocumentMut>()?;
+    doc["name"] = Item::Value(Value::from(package.name.as_str()));
+    doc["description"] = Item::Value(Value::from(description));
+    doc["license"] = Item::Value(Value::from(license));
+    if doc.get("versions").and_then(|v| v.as_array_of_tables()).is_none() {
+        doc["versions"] = Item::ArrayOfTables(ArrayOfTables::new());
+    }
+    let mut entry = Table::new();
+    entry.insert("version", Item::Value(Value::from(package.version.as_str())));
+    entry.insert("source", Item::Value(Value::from(package.source.as_str())));
+    entry.insert("checksum", Item::Value(Value::from(package.checksum.as_str())));
+    entry.insert("top_modules", Item::Value(Value::Array(package.top_modules.iter().map(|m| m.as_str()).collect::<Array>())));
+    doc["versions"].as_array_of_tables_mut().unwrap().push(entry);
+
+    fs::create_dir_all(entry_path.parent().unwrap())?;
+    fs::write(&entry_path, doc.to_string())?;
+    Ok(())
+}
+
+pub fn sha256_checksum(data: &[u8]) -> String {
+    format!("sha256:{:x}", Sha256::digest(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_ordered_by_semver() {
+        let mut versions = vec!["1.10.0", "1.2.0", "1.10.0-rc1", "0.9.9", "2.0.0+build", "latest"];
+        versions.retain(|v| version_key(v).is_some());
+        versions.sort_by_key(|v| version_key(v));
+        assert_eq!(versions, ["0.9.9", "1.2.0", "1.10.0-rc1", "1.10.0", "2.0.0+build"]);
+    }
+}
@@ -0,0 +1,132 @@
+use anyhow::Result;
+use std::fs;
+use std::path::Path;
+
+use crate::design::{parse_file_with_defines, ModuleInterface};
+use crate::preprocess::DefineSet;
+
+/// Which artifacts to generate in `headers/` for each vendored file.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StubKinds {
+    /// A `(* blackbox *)` module with the same parameters and ports, for synthesizing
+    /// around the module without its implementation.
+    pub blackbox: bool,
+    /// An instantiation with every parameter and port connection spelled out.
+    pub template: bool,
+    /// The parameters and ports as JSON, for scripts and other tools.
+    pub json: bool,
+}
+
+/// Writes the `kinds` of artifacts for each module declared in `source` to
+/// `<destination>/headers`, parsing it with `defines` if given. Returns the files written.
+pub fn write_stubs(source: &Path, destination: &Path, kinds: StubKinds, defines: Option<&DefineSet>) -> Result<Vec<String>> {
+    if !(kinds.blackbox || kinds.template || kinds.json) {
+        return Ok(Vec::new());
+    }
+    let modules = parse_file_with_defines(source, defines)?;
+    let extension = if source.extension().is_some_and(|ext| ext == "sv") { "sv" } else { "v" };
+    let headers_dir = destination.join("headers");
+    fs::create_dir_all(&headers_dir)?;
+
+    let mut written = Vec::new();
+    for module in &modules {
+        let mut outputs = Vec::new();
+        if kinds.blackbox {
+            outputs.push((format!("{}_blackbox.{}", module.name, extension), blackbox_stub(module)));
+        }
+        if kinds.template {
+            outputs.push((format!("{}_inst.{}h", module.name, extension), instantiation_template(module)));
+        }
+        if kinds.json {
+            outputs.push((format!("{}.json", module.name), serde_json::to_string_pretty(&interface_json(module))? + "\n"));
+        }
+        for (file_name, contents) in outputs {
+            let path = headers_dir.join(&file_name);
+            fs::write(&path, contents)?;
+            written.push(path.display().to_string());
+        }
+    }
+    Ok(written)
+}
+
+/// A module with the interface of `module` and an empty body, marked as a black box so
+/// synthesis keeps it as an opaque cell.
+pub fn blackbox_stub(module: &ModuleInterface) -> String {
+    let mut stub = format!("// Black box stub of '{}' generated by vpm\n(* blackbox *)\nmodule {}", module.name, module.name);
+    // Declarations are emitted as written; interfaces cached before they were recorded
+    // fall back to what the parameter and port lists hold.
+    let parameters: Vec<String> = if module.parameter_declarations.len() == module.parameters.len() {
+        module.parameter_declarations.iter().map(|declaration| format!("    {}", declaration)).collect()
+    } else {
+        module.parameters.iter()
+            .map(|(name, default)| match default {
+                Some(default) => format!("    parameter {} = {}", name, default),
+                None => format!("    parameter {}", name),
+            })
+            .collect()
+    };
+    if !parameters.is_empty() {
+        stub.push_str(&format!(" #(\n{}\n)", parameters.join(",\n")));
+    }
+    let ports: Vec<String> = if module.port_declarations.len() == module.ports.len() {
+        module.port_declarations.iter().map(|declaration| format!("    {}", declaration)).collect()
+    } else {
+        module.ports.iter()
+            .map(|(direction, width, name)| match width {
+                Some(width) => format!("    {} {} {}", direction, width, name),
+                None => format!("    {} {}", direction, name),
+            })
+            .collect()
+    };
+    if ports.is_empty() {
+        stub.push_str(";\n");
+    } else {
+        stub.push_str(&format!(" (\n{}\n);\n", ports.join(",\n")));
+    }
+    stub.push_str("endmodule\n");
+    stub
+}
+
+/// An instance of `module` with its parameters at their defaults and every port
+/// connected to nothing, commented with its direction and width.
+pub fn instantiation_template(module: &ModuleInterface) -> String {
+    let mut template = module.name.clone();
+    if !module.parameters.is_empty() {
+        let parameters: Vec<String> = module.parameters.iter()
+            .map(|(name, default)| format!("    .{}({})", name, default.as_deref().unwrap_or_default()))
+            .collect();
+        template.push_str(&format!(" #(\n{}\n)", parameters.join(",\n")));
+    }
+    template.push_str(&format!(" u_{} (\n", module.name));
+    let width = module.ports.iter().map(|(_, _, name)| name.len()).max().unwrap_or(0);
+    let count = module.ports.len();
+    for (index, (direction, port_width, name)) in module.ports.iter().enumerate() {
+        let separator = if index + 1 < count { "," } else { " " };
+        template.push_str(&format!(
+            "    .{:width$}(){} // {}{}\n",
+            name, separator, direction, port_width.as_ref().map(|w| format!(" {}", w)).unwrap_or_default(),
+            width = width
+        ));
+    }
+    template.push_str(");\n");
+    template
+}
+
+/// The parameters and ports of `module` as a JSON object.
+pub fn interface_json(module: &ModuleInterface) -> serde_json::Value {
+    serde_json::json!({
+        "module": module.name,
+        "parameters": module.parameters.iter()
+            .map(|(name, default)| serde_json::json!({ "name": name, "default": default }))
+            .collect::<Vec<_>>(),
+        "ports": module.ports.iter().enumerate()
+            .map(|(i, (direction, width, name))| serde_json::json!({
+                "name": name,
+                "direction": direction,
+                "width": width,
+                "declaration": module.port_declarations.get(i),
+            }))
+            .collect::<Vec<_>>(),
+        "submodules": module.submodules,
+    })
+}
@@ -0,0 +1,141 @@
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::design::{is_source, push_unique, DesignIndex};
+use crate::git::strip_credentials;
+use crate::lockfile::read_lockfile;
+use crate::preprocess::parse_define;
+use crate::primitives::blackbox_comment;
+use crate::toml::{get_dependency_targets, get_filesets, get_registry_dependencies};
+
+/// Targets vpm knows about. Other names are accepted but warned about, since they are
+/// usually typos.
+pub const KNOWN_TARGETS: [&str; 5] = ["sim", "synth", "fpga", "asic", "test"];
+
+/// Sources, include directories and defines selected for one target.
+#[derive(Debug, Clone, Default)]
+pub struct TargetSources {
+    pub files: Vec<PathBuf>,
+    pub include_dirs: Vec<PathBuf>,
+    pub defines: Vec<(String, Option<String>)>,
+    /// Vendor primitives instantiated by the selected dependencies.
+    pub blackboxes: Vec<String>,
+}
+
+impl TargetSources {
+    /// `+incdir+`/`+define+` lines followed by the files, as used in `.f` filelists, and a
+    /// comment naming the black boxes.
+    pub fn filelist_lines(&self) -> Vec<String> {
+        self.include_dirs.iter().map(|dir| format!("+incdir+{}", dir.display()))
+            .chain(self.defines.iter().map(|(name, value)| match value {
+                Some(value) => format!("+define+{}={}", name, value),
+                None => format!("+define+{}", name),
+            }))
+            .chain(self.files.iter().map(|file| file.display().to_string()))
+            .chain(blackbox_comment(&self.blackboxes))
+            .collect()
+    }
+
+    /// `-I`/`-D` options for tools with a C-like command line (Icarus Verilog, Yosys).
+    pub fn include_and_define_args(&self) -> Vec<String> {
+        self.include_dirs.iter().map(|dir| format!("-I{}", dir.display()))
+            .chain(self.defines.iter().map(|(name, value)| match value {
+                Some(value) => format!("-D{}={}", name, value),
+                None => format!("-D{}", name),
+            }))
+            .collect()
+    }
+}
+
+/// Selects the project's own filesets and the vendored dependencies that belong to `target`.
+pub fn select_sources(target: &str) -> Result<TargetSources> {
+    // Reading the manifest below would otherwise create an empty vpm.toml.
+    anyhow::ensure!(Path::new("vpm.toml").exists(), "No vpm.toml found in the current directory");
+    if !KNOWN_TARGETS.contains(&target) {
+        eprintln!("Warning: '{}' is not one of the known targets ({}).", target, KNOWN_TARGETS.join(", "));
+    }
+    let in_target = |targets: &[String]| targets.is_empty() || targets.iter().any(|t| t == target);
+    let mut sources = TargetSources::default();
+
+    let dependency_targets = get_dependency_targets();
+    let lock_entries = read_lockfile()?;
+    for (dependency, targets) in &dependency_targets {
+        if !in_target(targets) {
+            continue;
+        }
+        let source = strip_credentials(dependency);
+        for entry in lock_entries.iter().filter(|e| e.source == source) {
+            let path = PathBuf::from(&entry.full_path);
+            if !entry.includes.is_empty() {
+                if let Some(dir) = path.parent() {
+                    push_unique(&mut sources.include_dirs, dir.to_path_buf());
+                }
+            }
+            for dir in &entry.include_dirs {
+                push_unique(&mut sources.include_dirs, PathBuf::from(dir));
+            }
+            if path.is_file() && is_source(&path) {
+                push_unique(&mut sources.files, path);
+            }
+            for define in &entry.defines {
+                push_unique(&mut sources.defines, parse_define(define));
+            }
+            for blackbox in &entry.blackboxes {
+                push_unique(&mut sources.blackboxes, blackbox.clone());
+            }
+        }
+    }
+    for (name, _, _) in get_registry_dependencies() {
+        let selected = dependency_targets.iter().any(|(dependency, targets)| *dependency == name && in_target(targets));
+        if selected {
+            for file in rtl_files(&Path::new("vpm_modules").join(&name)) {
+                push_unique(&mut sources.files, file);
+            }
+        }
+    }
+
+    for fileset in get_filesets().into_iter().filter(|f| in_target(&f.targets)) {
+        for dir in fileset.include_dirs {
+            push_unique(&mut sources.include_dirs, PathBuf::from(dir));
+        }
+        for define in fileset.defines {
+            push_unique(&mut sources.defines, define);
+        }
+        for file in fileset.files {
+            let path = PathBuf::from(&file);
+            if path.is_dir() {
+                rtl_files(&path).into_iter().for_each(|f| push_unique(&mut sources.files, f));
+            } else if path.is_file() {
+                push_unique(&mut sources.files, path);
+            } else {
+                eprintln!("Warning: Source '{}' listed in vpm.toml does not exist.", file);
+            }
+        }
+    }
+    // Dependencies come before the files that use them, as in dotf filelists and exports.
+    sources.files = DesignIndex::from_files(&sources.files).sort_files(&sources.files);
+    Ok(sources)
+}
+
+fn rtl_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && is_source(e.path()))
+        .filter(|e| !e.path().components().any(|c| c.as_os_str() == "headers"))
+        .map(|e| e.into_path())
+        .collect();
+    files.sort();
+    files
+}
+
+/// Adds `file` to the selection unless it is already in it, comparing canonical paths.
+pub fn with_file(mut sources: TargetSources, file: &Path) -> TargetSources {
+    let canonical = fs::canonicalize(file).ok();
+    let present = sources.files.iter().any(|f| fs::canonicalize(f).ok() == canonical && canonical.is_some());
+    if !present {
+        sources.files.push(file.to_path_buf());
+    }
+    sources
+}
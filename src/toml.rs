@@ -3,17 +3,67 @@ use std::fs::{OpenOptions, read_to_string};
 use std::io::Write;
 use std::path::Path;
 use std::collections::HashSet;
+use std::sync::Mutex;
 use anyhow::Result;
 use toml_edit::{Array, DocumentMut, InlineTable, Item, Table, Value};
 
+use crate::git::strip_credentials;
+use crate::registry::sha256_checksum;
+
 
 #[derive(Serialize, Deserialize, Debug)]
-struct Package {
-    name: String,
-    version: String,
-    authors: Vec<String>,
-    description: String,
-    license: String,
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    pub authors: Vec<String>,
+    pub description: String,
+    pub license: String,
+}
+
+/// A group of the project's own sources from a `[[sources]]` table, optionally limited
+/// to some targets. A fileset without targets belongs to every target.
+#[derive(Debug, Clone, Default)]
+pub struct Fileset {
+    pub targets: Vec<String>,
+    pub files: Vec<String>,
+    pub include_dirs: Vec<String>,
+    pub defines: Vec<(String, Option<String>)>,
+}
+
+/// A dependency that only exists as generator source, from a `[generators.<name>]` table.
+/// `command` runs in the fetched repository and must write Verilog into `output`.
+#[derive(Debug, Clone, Default)]
+pub struct Generator {
+    pub name: String,
+    pub repo: String,
+    pub commit: String,
+    pub command: String,
+    pub output: String,
+    pub top_module: String,
+    pub parameters: Vec<(String, String)>,
+}
+
+/// Marks a complete cache entry of generator output; holds the hash of the commit wrapping
+/// the generated files.
+pub const CACHE_MARKER: &str = ".vpm-generated";
+
+impl Generator {
+    /// Identifies the output of a run at `commit` by everything that can change it.
+    pub fn cache_key(&self, commit: &str) -> String {
+        let mut parameters = self.parameters.clone();
+        parameters.sort();
+        let input = format!(
+            "{}\n{}\n{}\n{}\n{}",
+            strip_credentials(&self.repo), commit, self.command, self.output,
+            parameters.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("\n")
+        );
+        sha256_checksum(input.as_bytes()).trim_start_matches("sha256:")[..16].to_string()
+    }
+
+    /// Name of the cache directory holding the output of a run at `commit`.
+    pub fn cache_name(&self, commit: &str) -> String {
+        format!("{}-{}", self.name, self.cache_key(commit))
+    }
 }
 
 #[derive(Debug)]
@@ -61,6 +111,20 @@ impl VpmToml {
         }
     }
 
+    pub fn get_package(&self) -> Package {
+        let default = Package::default();
+        let field = |key: &str, default: String| self.toml_doc["package"][key].as_str().map(|s| s.to_string()).unwrap_or(default);
+        Package {
+            name: field("name", default.name),
+            version: field("version", default.version),
+            authors: self.toml_doc["package"]["authors"].as_array()
+                .map(|a| a.iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect())
+                .unwrap_or(default.authors),
+            description: field("description", default.description),
+            license: field("license", default.license),
+        }
+    }
+
     pub fn get_dependencies(&self) -> Option<&Table> {
         self.toml_doc["dependencies"].as_table()
     }
@@ -82,6 +146,21 @@ impl VpmToml {
         }
     }
 
+    pub fn add_registry_dependency(&mut self, name: &str, version: &str, top_modules: &[String]) {
+        let mut array = Array::new();
+        for top_module in top_modules {
+            let mut table = InlineTable::new();
+            table.insert("top_module".to_string(), Value::from(top_module.as_str()));
+            table.insert("version".to_string(), Value::from(version));
+            array.push(Value::InlineTable(table));
+        }
+        self.toml_doc["dependencies"][name] = Item::Value(Value::Array(array));
+    }
+
+    pub fn set_generator_commit(&mut self, name: &str, commit: &str) {
+        self.toml_doc["generators"][name]["commit"] = toml_edit::value(commit);
+    }
+
     pub fn remove_dependency(&mut self, git: &str) {
         if let Some(dependencies) = self.toml_doc["dependencies"].as_table_mut() {
             dependencies.remove(git);
@@ -144,9 +223,152 @@ impl VpmToml {
         }
         repo_links
     }
+
+    pub fn get_top_modules(&self) -> Vec<(String, String, String)> {
+        let mut top_modules = Vec::new();
+        if let Some(dependencies) = self.toml_doc["dependencies"].as_table() {
+            for (repo_link, dependency) in dependencies.iter() {
+                if let Some(modules) = dependency.as_array() {
+                    for module in modules.iter().filter_map(|m| m.as_inline_table()).filter(|m| !m.contains_key("version")) {
+                        let top_module = module.get("top_module").and_then(|v| v.as_str()).unwrap_or_default();
+                        let commit_hash = module.get("commit_hash").and_then(|v| v.as_str()).unwrap_or_default();
+                        top_modules.push((repo_link.to_string(), top_module.to_string(), commit_hash.to_string()));
+                    }
+                }
+            }
+        }
+        top_modules
+    }
+
+    pub fn get_filesets(&self) -> Vec<Fileset> {
+        let Some(sources) = self.toml_doc.get("sources").and_then(|s| s.as_array_of_tables()) else {
+            return Vec::new();
+        };
+        sources.iter().map(|source| {
+            let strings = |key: &str| -> Vec<String> {
+                match source.get(key) {
+                    Some(item) if item.is_str() => vec![item.as_str().unwrap().to_string()],
+                    Some(item) => item.as_array()
+                        .map(|a| a.iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect())
+                        .unwrap_or_default(),
+                    None => Vec::new(),
+                }
+            };
+            let defines = source.get("defines").and_then(|d| d.as_table_like()).map(|defines| {
+                defines.iter().map(|(name, value)| {
+                    let value = value.as_str().map(|s| s.to_string())
+                        .or_else(|| value.as_value().map(|v| v.to_string().trim().to_string()));
+                    (name.to_string(), value.filter(|v| !v.is_empty()))
+                }).collect()
+            }).unwrap_or_default();
+            Fileset {
+                targets: strings("target"),
+                files: strings("files"),
+                include_dirs: strings("include_dirs"),
+                defines,
+            }
+        }).collect()
+    }
+
+    pub fn get_search_paths(&self) -> Vec<String> {
+        self.get_resolve_strings("search_paths")
+    }
+
+    pub fn get_primitives(&self) -> Vec<String> {
+        self.get_resolve_strings("primitives")
+    }
+
+    pub fn get_lint_waivers(&self) -> Vec<String> {
+        self.toml_doc.get("lint").and_then(|l| l.get("waivers")).and_then(|w| w.as_array())
+            .map(|a| a.iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect())
+            .unwrap_or_default()
+    }
+
+    fn get_resolve_strings(&self, key: &str) -> Vec<String> {
+        self.toml_doc.get("resolve").and_then(|r| r.get(key)).and_then(|p| p.as_array())
+            .map(|a| a.iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn get_generators(&self) -> Vec<Generator> {
+        let Some(generators) = self.toml_doc.get("generators").and_then(|g| g.as_table_like()) else {
+            return Vec::new();
+        };
+        generators.iter().map(|(name, generator)| {
+            let field = |key: &str| generator.get(key).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+            let parameters = generator.get("parameters").and_then(|p| p.as_table_like()).map(|parameters| {
+                parameters.iter().map(|(key, value)| {
+                    let value = value.as_str().map(|s| s.to_string())
+                        .or_else(|| value.as_value().map(|v| v.to_string().trim().to_string()))
+                        .unwrap_or_default();
+                    (key.to_string(), value)
+                }).collect()
+            }).unwrap_or_default();
+            Generator {
+                name: name.to_string(),
+                repo: field("repo"),
+                commit: field("commit"),
+                command: field("command"),
+                output: field("output"),
+                top_module: field("top_module"),
+                parameters,
+            }
+        }).collect()
+    }
+
+    /// Returns the targets each dependency is limited to. An empty list means every target.
+    pub fn get_dependency_targets(&self) -> Vec<(String, Vec<String>)> {
+        let mut dependency_targets = Vec::new();
+        if let Some(dependencies) = self.toml_doc["dependencies"].as_table() {
+            for (name, dependency) in dependencies.iter() {
+                let modules: Vec<_> = dependency.as_array()
+                    .map(|modules| modules.iter().filter_map(|m| m.as_inline_table()).collect())
+                    .unwrap_or_default();
+                let mut targets = Vec::new();
+                for module in &modules {
+                    match module.get("target") {
+                        Some(target) if target.is_str() => targets.push(target.as_str().unwrap().to_string()),
+                        Some(target) => targets.extend(target.as_array().into_iter().flatten().filter_map(|t| t.as_str().map(|s| s.to_string()))),
+                        // An entry without a target is needed everywhere.
+                        None => {
+                            targets.clear();
+                            break;
+                        }
+                    }
+                }
+                dependency_targets.push((name.to_string(), targets));
+            }
+        }
+        dependency_targets
+    }
+
+    pub fn get_registry_dependencies(&self) -> Vec<(String, String, Vec<String>)> {
+        let mut packages = Vec::new();
+        if let Some(dependencies) = self.toml_doc["dependencies"].as_table() {
+            for (name, dependency) in dependencies.iter() {
+                let modules: Vec<_> = dependency.as_array()
+                    .map(|modules| modules.iter().filter_map(|m| m.as_inline_table()).collect())
+                    .unwrap_or_default();
+                let version = modules.iter().find_map(|m| m.get("version")?.as_str());
+                if let Some(version) = version {
+                    let top_modules = modules.iter()
+                        .filter_map(|m| m.get("top_module")?.as_str().map(|s| s.to_string()))
+                        .collect();
+                    packages.push((name.to_string(), version.to_string(), top_modules));
+                }
+            }
+        }
+        packages
+    }
 }
 
+/// Held while vpm.toml is read, changed and written back, so modules included in
+/// parallel do not overwrite each other's entries.
+static TOML_WRITES: Mutex<()> = Mutex::new(());
+
 pub fn add_dependency(git: &str) -> Result<()> {
+    let git = &strip_credentials(git);
+    let _guard = TOML_WRITES.lock().unwrap_or_else(|e| e.into_inner());
     let mut vpm_toml = VpmToml::from("vpm.toml");
     if !vpm_toml.get_dependencies().unwrap().contains_key(git) {
         vpm_toml.add_dependency(git);
@@ -156,12 +378,23 @@ pub fn add_dependency(git: &str) -> Result<()> {
 }
 
 pub fn add_top_module(repo_link: &str, module_path: &str, commit: &str) -> Result<()> {
+    let repo_link = &strip_credentials(repo_link);
+    let _guard = TOML_WRITES.lock().unwrap_or_else(|e| e.into_inner());
     let mut vpm_toml = VpmToml::from("vpm.toml");
     vpm_toml.add_top_module(repo_link, module_path, commit);
     vpm_toml.write_to_file("vpm.toml")?;
     Ok(())
 }
 
+/// Pins the generator `name` to `commit`, so later runs build the same sources.
+pub fn set_generator_commit(name: &str, commit: &str) -> Result<()> {
+    let _guard = TOML_WRITES.lock().unwrap_or_else(|e| e.into_inner());
+    let mut vpm_toml = VpmToml::from("vpm.toml");
+    vpm_toml.set_generator_commit(name, commit);
+    vpm_toml.write_to_file("vpm.toml")?;
+    Ok(())
+}
+
 fn remove_dependency(git: &str) -> Result<()> {
     let mut vpm_toml = VpmToml::from("vpm.toml");
     vpm_toml.remove_dependency(git);
@@ -186,4 +419,69 @@ pub fn remove_top_module(repo_link: &str, module_name: &str) -> Result<()> {
 pub fn get_repo_links(module_name: &str) -> HashSet<String> {
     let vpm_toml = VpmToml::from("vpm.toml");
     vpm_toml.get_repo_links(module_name)
-}
\ No newline at end of file
+}
+pub fn get_package() -> Package {
+    let vpm_toml = VpmToml::from("vpm.toml");
+    vpm_toml.get_package()
+}
+
+pub fn add_registry_dependency(name: &str, version: &str, top_modules: &[String]) -> Result<()> {
+    let _guard = TOML_WRITES.lock().unwrap_or_else(|e| e.into_inner());
+    let mut vpm_toml = VpmToml::from("vpm.toml");
+    vpm_toml.add_registry_dependency(name, version, top_modules);
+    vpm_toml.write_to_file("vpm.toml")?;
+    Ok(())
+}
+
+/// Returns every `(name, version, top_modules)` registry package in vpm.toml, in file order.
+pub fn get_registry_dependencies() -> Vec<(String, String, Vec<String>)> {
+    let vpm_toml = VpmToml::from("vpm.toml");
+    vpm_toml.get_registry_dependencies()
+}
+
+/// Returns every `(repo_link, top_module, commit_hash)` git entry in vpm.toml, in file order.
+pub fn get_top_modules() -> Vec<(String, String, String)> {
+    let vpm_toml = VpmToml::from("vpm.toml");
+    vpm_toml.get_top_modules()
+}
+
+/// Returns the `[[sources]]` filesets of vpm.toml, in file order.
+pub fn get_filesets() -> Vec<Fileset> {
+    let vpm_toml = VpmToml::from("vpm.toml");
+    vpm_toml.get_filesets()
+}
+
+/// Returns `(dependency, targets)` for every dependency in vpm.toml. An empty list means every target.
+pub fn get_dependency_targets() -> Vec<(String, Vec<String>)> {
+    let vpm_toml = VpmToml::from("vpm.toml");
+    vpm_toml.get_dependency_targets()
+}
+
+/// Returns the `[generators]` entries of vpm.toml, in file order.
+pub fn get_generators() -> Vec<Generator> {
+    let vpm_toml = VpmToml::from("vpm.toml");
+    vpm_toml.get_generators()
+}
+
+/// Returns the `[resolve] search_paths` of vpm.toml: repository directories preferred when
+/// several files declare the same module.
+pub fn get_search_paths() -> Vec<String> {
+    let vpm_toml = VpmToml::from("vpm.toml");
+    vpm_toml.get_search_paths()
+}
+
+/// Returns the `[resolve] primitives` of vpm.toml: cells, or `PREFIX*` patterns, that come
+/// from a vendor library and are never fetched.
+pub fn get_primitives() -> Vec<String> {
+    let vpm_toml = VpmToml::from("vpm.toml");
+    vpm_toml.get_primitives()
+}
+
+/// Returns the `[lint] waivers` of vpm.toml: files of `[[waiver]]` entries for `vpm lint`.
+pub fn get_lint_waivers() -> Vec<String> {
+    if !Path::new("vpm.toml").exists() {
+        return Vec::new();
+    }
+    let vpm_toml = VpmToml::from("vpm.toml");
+    vpm_toml.get_lint_waivers()
+}
@@ -0,0 +1,247 @@
+use anyhow::{Context, Result};
+use git2::build::CheckoutBuilder;
+use git2::Repository;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use toml_edit::{ArrayOfTables, DocumentMut, Item, Table, Value};
+
+use crate::config_man::{cache_dir, get_registry_index};
+use crate::git::{clone_repo, get_remote_head, name_from_url, strip_credentials};
+use crate::lockfile::read_lockfile;
+use crate::registry::{add_index_entry, fetch_archive, resolve, sha256_checksum, unpack_archive, PackageVersion};
+use crate::toml::{get_generators, get_registry_dependencies, get_top_modules, CACHE_MARKER};
+
+const ARCHIVE_ROOT: &str = "vpm-vendor";
+
+/// An extracted vendor archive, used in place of the network by the commands given one.
+#[derive(Debug)]
+pub struct VendorArchive {
+    pub root: PathBuf,
+    /// `(url, commit, path)` of every repository snapshot in the archive.
+    pub repositories: Vec<(String, String, PathBuf)>,
+    /// The registry index the bundled registry packages were originally resolved from.
+    pub registry_index: String,
+}
+
+/// Bundles vpm.toml, vpm.lock, every dependency at its locked commit and all locally
+/// modified vendored files into a self-contained archive at `output`.
+pub async fn create_archive(output: &Path) -> Result<()> {
+    anyhow::ensure!(Path::new("vpm.toml").exists(), "No vpm.toml found in the current directory");
+    let staging = tempfile::tempdir()?;
+    let root = staging.path().join(ARCHIVE_ROOT);
+    fs::create_dir_all(&root)?;
+    fs::copy("vpm.toml", root.join("vpm.toml"))?;
+    if Path::new("vpm.lock").exists() {
+        fs::copy("vpm.lock", root.join("vpm.lock"))?;
+    }
+
+    let mut manifest = DocumentMut::new();
+    manifest["registry_index"] = Item::Value(Value::from(get_registry_index()));
+    manifest["repository"] = Item::ArrayOfTables(ArrayOfTables::new());
+
+    let mut sources: Vec<(String, String)> = get_top_modules().into_iter()
+        .map(|(repo_link, _, commit_hash)| (strip_credentials(&repo_link), commit_hash))
+        .collect();
+    let generators = get_generators();
+    sources.extend(generators.iter().map(|g| (strip_credentials(&g.repo), g.commit.clone())));
+    sources.sort();
+    sources.dedup();
+    let mut bundled = Vec::new();
+    for (url, commit_hash) in &sources {
+        let pb = ProgressBar::new_spinner();
+        pb.set_style(ProgressStyle::default_spinner().template("{spinner} {msg}").unwrap());
+        pb.set_message(format!("Fetching {}...", url));
+        pb.enable_steady_tick(Duration::from_millis(100));
+        let short_hash = &commit_hash[..commit_hash.len().min(7)];
+        let path = PathBuf::from("repos").join(format!("{}-{}", name_from_url(url), short_hash));
+        // A full checkout, since a partial clone would leave the files to fetch later.
+        let commit = clone_repo(url, &root.join(&path), Some(commit_hash.as_str()).filter(|c| !c.is_empty()), false, Some(&pb))
+            .with_context(|| format!("Failed to fetch '{}'", url))?;
+        pb.finish_with_message(format!("Bundled {}@{}", url, &commit[..7]));
+
+        let mut entry = Table::new();
+        entry.insert("url", Item::Value(Value::from(url.as_str())));
+        entry.insert("commit", Item::Value(Value::from(commit.as_str())));
+        entry.insert("path", Item::Value(Value::from(path.to_string_lossy().as_ref())));
+        manifest["repository"].as_array_of_tables_mut().unwrap().push(entry);
+        bundled.push((url, commit_hash, commit));
+    }
+
+    // Generated outputs are shipped as well, so syncing from the archive need not rerun
+    // the generators.
+    for generator in &generators {
+        let url = strip_credentials(&generator.repo);
+        let commit = match bundled.iter().find(|(u, c, _)| **u == url && **c == generator.commit) {
+            Some((_, _, commit)) if generator.commit.is_empty() => commit.clone(),
+            _ => generator.commit.clone(),
+        };
+        let cached = cache_dir()?.join("generated").join(generator.cache_name(&commit));
+        if cached.join(CACHE_MARKER).exists() {
+            copy_dir(&cached, &root.join("generated").join(generator.cache_name(&commit)))?;
+            println!("Bundled output of generator '{}'", generator.name);
+        }
+    }
+
+    let registry_dir = root.join("registry");
+    for (name, version, _) in get_registry_dependencies() {
+        let (index_dir, package) = resolve(&name, Some(&version), None)?;
+        let archive = fetch_archive(&index_dir, &package).await?;
+        let source = format!("archives/{}-{}.tar.gz", package.name, package.version);
+        fs::create_dir_all(registry_dir.join("archives"))?;
+        fs::write(registry_dir.join(&source), &archive)?;
+        add_index_entry(&registry_dir, &PackageVersion { source, ..package }, "", "")?;
+        println!("Bundled {}@{}", name, version);
+    }
+
+    // Vendored files that no longer match their locked checksum are local patches;
+    // they are shipped as-is and restored over the synced sources.
+    let mut patches = 0;
+    for entry in read_lockfile()? {
+        let Some(checksum) = &entry.checksum else { continue };
+        let Ok(contents) = fs::read(&entry.full_path) else { continue };
+        if &sha256_checksum(&contents) == checksum {
+            continue;
+        }
+        let relative: PathBuf = Path::new(&entry.full_path).components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect();
+        let target = root.join("patches").join(&relative);
+        fs::create_dir_all(target.parent().unwrap())?;
+        fs::write(target, contents)?;
+        println!("Bundled local patch to {}", relative.display());
+        patches += 1;
+    }
+
+    fs::write(root.join("vendor.toml"), manifest.to_string())?;
+
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    builder.append_dir_all(ARCHIVE_ROOT, &root)?;
+    let archive = builder.into_inner()?.finish()?;
+    fs::write(output, &archive)
+        .with_context(|| format!("Failed to write archive '{}'", output.display()))?;
+
+    println!(
+        "Created vendor archive {} ({} repositories, {} registry packages, {} patches)",
+        output.display(), sources.len(), get_registry_dependencies().len(), patches
+    );
+    Ok(())
+}
+
+/// Extracts the vendor archive at `path` into the cache directory and opens it.
+pub fn open_archive(path: &Path) -> Result<VendorArchive> {
+    let archive = fs::read(path).with_context(|| format!("Failed to read vendor archive '{}'", path.display()))?;
+    let checksum = sha256_checksum(&archive);
+    let root = cache_dir()?
+        .join("vendor")
+        .join(&checksum.trim_start_matches("sha256:")[..16]);
+    if !root.join("vendor.toml").exists() {
+        unpack_archive(&archive, &root)?;
+    }
+
+    let manifest = fs::read_to_string(root.join("vendor.toml"))
+        .context("Vendor archive is missing vendor.toml")?
+        .parse::<DocumentMut>()
+        .context("Failed to parse vendor.toml")?;
+    let repositories = manifest.get("repository").and_then(|r| r.as_array_of_tables()).map_or_else(Vec::new, |repositories| {
+        repositories.iter().map(|entry| {
+            let field = |key: &str| entry.get(key).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+            (field("url"), field("commit"), root.join(field("path")))
+        }).collect()
+    });
+    let registry_index = manifest.get("registry_index").and_then(|v| v.as_str()).unwrap_or_default().to_string();
+
+    println!("Using vendor archive {} ({} repositories)", path.display(), repositories.len());
+    Ok(VendorArchive { root, repositories, registry_index })
+}
+
+/// Returns the commit `url` is at: its remote HEAD, or the commit it is bundled at in
+/// `archive`.
+pub fn remote_head(archive: Option<&VendorArchive>, url: &str) -> Result<String> {
+    let Some(archive) = archive else { return get_remote_head(url) };
+    archive.find_repository(url, None)
+        .map(|(_, commit, _)| commit.clone())
+        .with_context(|| format!("'{}' is not in the vendor archive, or is bundled at more than one commit", url))
+}
+
+/// Clones `url` like `clone_repo`, or copies its snapshot out of `archive` when one is given.
+pub fn fetch_repository(archive: Option<&VendorArchive>, url: &str, repo_path: &Path, commit_hash: Option<&str>, sparse: bool, progress: Option<&ProgressBar>) -> Result<String> {
+    let Some(archive) = archive else { return clone_repo(url, repo_path, commit_hash, sparse, progress) };
+    if repo_path.exists() {
+        fs::remove_dir_all(repo_path)?;
+    }
+    let (_, commit, snapshot) = archive.find_repository(url, commit_hash)
+        .with_context(|| format!("'{}' at commit '{}' is not in the vendor archive", url, commit_hash.unwrap_or("HEAD")))?;
+    copy_dir(snapshot, repo_path)?;
+    if !sparse {
+        Repository::open(repo_path)?.checkout_head(Some(CheckoutBuilder::new().force()))
+            .with_context(|| format!("Failed to checkout commit hash: '{}'", commit))?;
+    }
+    Ok(commit.clone())
+}
+
+impl VendorArchive {
+    /// Finds the snapshot of `url` at `commit_hash`, or its only snapshot if no commit is given.
+    pub fn find_repository(&self, url: &str, commit_hash: Option<&str>) -> Option<&(String, String, PathBuf)> {
+        let url = strip_credentials(url);
+        let mut candidates = self.repositories.iter().filter(|(u, _, _)| *u == url);
+        match commit_hash {
+            Some(hash) => candidates.find(|(_, commit, _)| commit.starts_with(&hash.to_lowercase())),
+            None => {
+                let first = candidates.next()?;
+                candidates.next().is_none().then_some(first)
+            }
+        }
+    }
+
+    /// Directory holding the bundled registry index.
+    pub fn registry_dir(&self) -> PathBuf {
+        self.root.join("registry")
+    }
+
+    /// Directory holding the bundled output of a generator, by its cache name.
+    pub fn generated_dir(&self, cache_name: &str) -> PathBuf {
+        self.root.join("generated").join(cache_name)
+    }
+
+    /// Copies the bundled vpm.toml and vpm.lock into the current directory if it has none.
+    pub fn restore_manifests(&self) -> Result<()> {
+        for file in ["vpm.toml", "vpm.lock"] {
+            if !Path::new(file).exists() && self.root.join(file).exists() {
+                fs::copy(self.root.join(file), file)?;
+                println!("Restored {} from the vendor archive", file);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the bundled local patches over the vendored files.
+    pub fn apply_patches(&self) -> Result<()> {
+        let patches_dir = self.root.join("patches");
+        for entry in walkdir::WalkDir::new(&patches_dir).into_iter().filter_map(|e| e.ok()).filter(|e| e.file_type().is_file()) {
+            let relative = entry.path().strip_prefix(&patches_dir)?;
+            if let Some(parent) = relative.parent().filter(|p| !p.as_os_str().is_empty()) {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(entry.path(), relative)?;
+            println!("Applied local patch to {}", relative.display());
+        }
+        Ok(())
+    }
+}
+
+/// Recursively copies the directory `from` to `to`.
+pub fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    for entry in walkdir::WalkDir::new(from).into_iter().filter_map(|e| e.ok()) {
+        let target = to.join(entry.path().strip_prefix(from)?);
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,16 @@
+module lanes #(parameter N = 4, parameter USE_EXTRA = 1) (input clk);
+  generate
+    for (genvar i = 0; i < N; i++) begin : g_lane
+      lane u_lane (.clk(clk), .index(i));
+    end
+    if (USE_EXTRA) begin : g_extra
+      extra u_extra (.clk(clk));
+    end else begin : g_plain
+      plain u_plain (.clk(clk));
+    end
+  endgenerate
+
+  for (genvar j = 0; j < 2; j++) begin : g_bare
+    bare u_bare (.clk(clk));
+  end
+endmodule
@@ -0,0 +1,4 @@
+module arrays (input [3:0] a, output [3:0] y, output [1:0] z);
+  buffer u_buf[3:0] (.a(a), .y(y));
+  inverter u_inv0 (.a(a[0]), .y(z[0])), u_inv1 (.a(a[1]), .y(z[1]));
+endmodule
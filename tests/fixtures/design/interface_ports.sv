@@ -0,0 +1,7 @@
+module bridge (
+  input logic clk,
+  bus_if.master upstream,
+  bus_if.slave downstream
+);
+  arbiter u_arbiter (.clk(clk), .bus(upstream));
+endmodule
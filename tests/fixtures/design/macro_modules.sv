@@ -0,0 +1,8 @@
+`ifndef FIFO_IMPL
+  `define FIFO_IMPL sync_fifo
+`endif
+
+module macro_top (input clk);
+  `FIFO_IMPL u_fifo (.clk(clk));
+  counter u_counter (.clk(clk));
+endmodule
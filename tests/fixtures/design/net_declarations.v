@@ -0,0 +1,5 @@
+module nets (input [3:0] a, input [3:0] b, output [3:0] y);
+  wire [3:0] x = a & b;
+  wire [3:0] w = x | b;
+  assign y = w;
+endmodule
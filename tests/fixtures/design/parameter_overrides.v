@@ -0,0 +1,15 @@
+module overrides (input clk, input [7:0] din, output [7:0] dout);
+  fifo #(
+    .WIDTH(8),
+    .DEPTH(16)
+  ) u_fifo (
+    .clk(clk),
+    .din(din),
+    .dout(dout)
+  );
+
+  counter #(
+    4,
+    10
+  ) u_counter (.clk(clk));
+endmodule